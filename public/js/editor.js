@@ -0,0 +1,18 @@
+// Refresh the preview of the page a moment after typing stops
+(function () {
+  var form = document.querySelector("form.editor");
+  if (!form) {
+    return;
+  }
+  var source = form.querySelector("textarea[name=source]");
+  var preview = form.querySelector("button[formtarget=preview]");
+  var timeout = null;
+
+  source.addEventListener("input", function () {
+    clearTimeout(timeout);
+    timeout = setTimeout(function () {
+      form.requestSubmit(preview);
+    }, 1000);
+  });
+  form.requestSubmit(preview);
+})();
@@ -0,0 +1,93 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, io, process};
+
+use image::imageops::FilterType;
+use image::{ImageError, ImageFormat};
+use rocket::fs::FileName;
+
+use crate::page::{Loaded, Page};
+
+/// Widths that images are resized to for responsive `srcset`s. Requests for other sizes are
+/// refused so that the on-disk cache can't be filled with arbitrary variants.
+pub(crate) const RESPONSIVE_WIDTHS: [u32; 3] = [480, 960, 1440];
+
+/// The page that an attachment belongs to, when it is in a folder named after the page.
+/// Attachments are hidden and private along with their page.
+pub(crate) fn owning_page(path: &Path, pages_path: &Path) -> Option<Page<Loaded>> {
+    path.parent()
+        .and_then(|dir| dir.iter().next())
+        .and_then(OsStr::to_str)
+        .and_then(|name| Page::new(FileName::new(name), pages_path))
+        .and_then(|page| page.load().ok())
+}
+
+// Distinguishes the temporary files of concurrent resizes
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Can this image be resized. Animated formats like GIF and vector formats are excluded.
+pub(crate) fn is_resizable(path: &Path) -> bool {
+    matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP)
+    )
+}
+
+/// Read the width and height of an image from its header
+pub(crate) fn dimensions(path: &Path) -> io::Result<(u32, u32)> {
+    image::image_dimensions(path).map_err(image_error)
+}
+
+/// Write a copy of `source` resized to `width` to `cache_path`.
+///
+/// The image is only resized if the cached copy is missing or older than `source`.
+pub(crate) fn resize(source: &Path, width: u32, cache_path: &Path) -> io::Result<()> {
+    let source_mtime = fs::metadata(source)?.modified()?;
+    let is_fresh = fs::metadata(cache_path)
+        .and_then(|meta| meta.modified())
+        .map_or(false, |mtime| mtime >= source_mtime);
+    if is_fresh {
+        return Ok(());
+    }
+
+    let format = ImageFormat::from_path(source).map_err(image_error)?;
+    let image = image::open(source).map_err(image_error)?;
+    // Never scale up
+    let image = if image.width() > width {
+        image.resize(width, u32::MAX, FilterType::Lanczos3)
+    } else {
+        image
+    };
+
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first so that a partially written image is never served. Each
+    // request uses its own file as the same image may be resized by concurrent requests.
+    let file_name = cache_path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or("image");
+    let tmp_path = cache_path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = image
+        .save_with_format(&tmp_path, format)
+        .map_err(image_error)
+        .and_then(|()| fs::rename(&tmp_path, cache_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn image_error(err: ImageError) -> io::Error {
+    match err {
+        ImageError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
@@ -0,0 +1,208 @@
+//! Recording changes made to the pages through the web as git commits, so they can be audited
+//! and reverted with the usual git tools
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use git2::{ErrorCode, Index, IndexEntry, IndexTime, Oid, Repository, Signature};
+
+use crate::settings::Settings;
+
+// Commits update the index and HEAD so only make one at a time
+static COMMIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Commit the current state of the page file at `path`, which may have been removed, if
+/// committing is enabled. Failures are logged, the change to the page has already been made.
+pub(crate) fn record(settings: &Settings, path: &Path, user: &str, message: &str) {
+    if !settings.git.commit {
+        return;
+    }
+    if let Err(err) = commit(&settings.pages_path, &settings.domain, path, user, message) {
+        error!("unable to commit {}: {}", path.display(), err);
+    }
+}
+
+fn commit(
+    pages_path: &Path,
+    domain: &str,
+    path: &Path,
+    user: &str,
+    message: &str,
+) -> Result<(), git2::Error> {
+    let _lock = COMMIT_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    // The pages may be in a subdirectory of an existing repository
+    let repo = match Repository::discover(pages_path) {
+        Ok(repo) => repo,
+        Err(err) if err.code() == ErrorCode::NotFound => {
+            info!("creating git repository in {}", pages_path.display());
+            Repository::init(pages_path)?
+        }
+        Err(err) => return Err(err),
+    };
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("repository has no working directory"))?;
+    let relative = canonical_path(path)?
+        .strip_prefix(canonical_path(workdir)?)
+        .map_err(|_| git2::Error::from_str("page is outside of the repository"))?
+        .to_path_buf();
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        // The first commit in a new repository
+        Err(err) if matches!(err.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => None,
+        Err(err) => return Err(err),
+    };
+
+    // The tree is HEAD's with just this page changed, so that anything else staged in the
+    // repository isn't committed along with it
+    let mut index = Index::new()?;
+    if let Some(parent) = &parent {
+        index.read_tree(&parent.tree()?)?;
+    }
+    if path.exists() {
+        index.add(&index_entry(&relative, repo.blob_path(path)?)?)?;
+    } else {
+        index.remove_path(&relative)?;
+    }
+    let tree = repo.find_tree(index.write_tree_to(&repo)?)?;
+
+    let author = Signature::now(user, &email(user, domain))?;
+    let committer = Signature::now("pkb", &format!("pkb@{}", domain))?;
+    repo.commit(
+        Some("HEAD"),
+        &author,
+        &committer,
+        message,
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )?;
+
+    // Stage the page too, otherwise the repository's index would still have the previous
+    // version and git would show the commit being undone
+    let mut staged = repo.index()?;
+    if path.exists() {
+        staged.add_path(&relative)?;
+    } else {
+        staged.remove_path(&relative)?;
+    }
+    staged.write()
+}
+
+// Removed pages can't be canonicalised, so their directory is instead
+fn canonical_path(path: &Path) -> Result<PathBuf, git2::Error> {
+    let canonical = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if !path.exists() => dir.canonicalize().map(|dir| dir.join(name)),
+        _ => path.canonicalize(),
+    };
+    canonical.map_err(|err| git2::Error::from_str(&err.to_string()))
+}
+
+// An entry for a regular file in an index, paths in the index are always separated by /
+fn index_entry(relative: &Path, id: Oid) -> Result<IndexEntry, git2::Error> {
+    let path = relative
+        .to_str()
+        .ok_or_else(|| git2::Error::from_str("page path is not UTF-8"))?
+        .replace('\\', "/");
+    Ok(IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0o100644,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id,
+        flags: 0,
+        flags_extended: 0,
+        path: path.into_bytes(),
+    })
+}
+
+// Users are often identified by their email address already, otherwise make one up on the
+// site's domain
+fn email(user: &str, domain: &str) -> String {
+    if user.contains('@') {
+        user.to_string()
+    } else {
+        format!("{}@{}", user, domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use git2::Sort;
+    use std::fs;
+
+    #[test]
+    fn commits_changes() {
+        let dir = TempDir::new("history");
+        let pages_path = dir.path();
+        let path = pages_path.join("page.md");
+        let commit = |user, message| commit(pages_path, "example.com", &path, user, message);
+
+        fs::write(&path, "First").unwrap();
+        commit("someone", "Create page").unwrap();
+        fs::write(&path, "Second").unwrap();
+        commit("a@example.org", "Fix typo").unwrap();
+        fs::remove_file(&path).unwrap();
+        commit("someone", "Delete page").unwrap();
+
+        // Newest first
+        let repo = Repository::open(pages_path).unwrap();
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.set_sorting(Sort::TOPOLOGICAL).unwrap();
+        revwalk.push_head().unwrap();
+        let commits = revwalk
+            .map(|oid| {
+                let commit = repo.find_commit(oid.unwrap()).unwrap();
+                let tree = commit.tree().unwrap();
+                (
+                    commit.summary().unwrap().to_string(),
+                    commit.author().email().unwrap().to_string(),
+                    tree.get_name("page.md").is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let expected = |summary: &str, email: &str, has_page| {
+            (summary.to_string(), email.to_string(), has_page)
+        };
+        assert_eq!(
+            commits,
+            [
+                expected("Delete page", "someone@example.com", false),
+                expected("Fix typo", "a@example.org", true),
+                expected("Create page", "someone@example.com", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn commits_only_the_page() {
+        let dir = TempDir::new("history-subdir");
+        let repo = Repository::init(dir.path()).unwrap();
+        let pages_path = dir.path().join("pages");
+        fs::create_dir(&pages_path).unwrap();
+        // Someone else's change that is staged but not committed yet
+        fs::write(dir.path().join("other.txt"), "Other").unwrap();
+        let mut staged = repo.index().unwrap();
+        staged.add_path(Path::new("other.txt")).unwrap();
+        staged.write().unwrap();
+
+        let path = pages_path.join("page.md");
+        fs::write(&path, "Content").unwrap();
+        commit(&pages_path, "example.com", &path, "someone", "Create page").unwrap();
+
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        assert!(tree.get_path(Path::new("pages/page.md")).is_ok());
+        assert!(tree.get_name("other.txt").is_none());
+        // Both remain staged
+        let staged = repo.index().unwrap();
+        assert!(staged.get_path(Path::new("pages/page.md"), 0).is_some());
+        assert!(staged.get_path(Path::new("other.txt"), 0).is_some());
+    }
+}
@@ -9,11 +9,19 @@ use rocket::Request;
 use std::{fmt, io};
 use time::{OffsetDateTime, Time};
 
+mod attachment;
+mod history;
 mod page;
+mod purge;
+mod related;
+pub mod render_cache;
+mod report;
 mod settings;
 pub mod string_ext;
 mod tag;
 pub(crate) mod templates;
+#[cfg(test)]
+mod test_support;
 pub mod web;
 
 include!(concat!(env!("OUT_DIR"), "/build_date.rs")); // generated by build.rs
@@ -23,6 +31,12 @@ pub enum PkbError {
     Io(io::Error),
     /// Page is invalid or not found
     PageNotFound,
+    /// Page is private and the request was not authenticated
+    Unauthorized,
+    /// Page was changed by someone else, or already exists
+    Conflict,
+    /// Page was modified after the time in If-Unmodified-Since
+    PreconditionFailed,
 }
 
 pub trait OffsetDateTimeExt {
@@ -41,11 +55,26 @@ impl From<io::Error> for PkbError {
     }
 }
 
+impl From<page::SaveError> for PkbError {
+    fn from(err: page::SaveError) -> Self {
+        match err {
+            page::SaveError::Conflict | page::SaveError::Exists => PkbError::Conflict,
+            page::SaveError::InvalidName(reason) => {
+                PkbError::Io(io::Error::new(io::ErrorKind::InvalidInput, reason))
+            }
+            page::SaveError::Io(err) => PkbError::Io(err),
+        }
+    }
+}
+
 impl fmt::Display for PkbError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PkbError::Io(err) => err.fmt(f),
             PkbError::PageNotFound => f.write_str("page not found"),
+            PkbError::Unauthorized => f.write_str("authentication required"),
+            PkbError::Conflict => f.write_str("page was changed or already exists"),
+            PkbError::PreconditionFailed => f.write_str("page was modified since the given time"),
         }
     }
 }
@@ -61,6 +90,9 @@ impl<'r> Responder<'r, 'static> for PkbError {
     fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
         match self {
             PkbError::PageNotFound => Err(Status::NotFound),
+            PkbError::Unauthorized => Err(Status::Unauthorized),
+            PkbError::Conflict => Err(Status::Conflict),
+            PkbError::PreconditionFailed => Err(Status::PreconditionFailed),
             _ => {
                 error!("{}: {}", req.uri(), self);
                 sentry::capture_error(&self);
@@ -72,8 +104,8 @@ impl<'r> Responder<'r, 'static> for PkbError {
 
 #[macro_export]
 macro_rules! return_if_fresh {
-    ($since:ident, $exp:expr) => {
-        if let Some(not_modified) = $since.map_or(None, |since| since.is_fresh($exp)) {
+    ($since:ident, $exp:expr, $policy:expr) => {
+        if let Some(not_modified) = $since.map_or(None, |since| since.is_fresh($policy, $exp)) {
             return Ok(not_modified);
         }
     };
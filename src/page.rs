@@ -1,21 +1,38 @@
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
 use std::fmt::Debug;
-use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 use std::time::SystemTime;
-use std::{fs, io};
+use std::{fs, io, process};
 
 use rocket::fs::FileName;
-use serde::Deserialize;
+use rocket::http::RawStr;
+use serde::{Deserialize, Serialize};
 use time::format_description::well_known::Rfc3339;
 use time::format_description::FormatItem;
 use time::macros::format_description;
-use time::OffsetDateTime;
+use time::{Date, OffsetDateTime};
 use titlecase::titlecase;
 
+use crate::settings::{MarkdownOptions, MarkdownOverrides, Settings};
+use crate::string_ext::StringExt;
 use crate::OffsetDateTimeExt;
 
 const YAML_BOUNDARY: &str = "---";
 
+/// Names of the site's own pages, like `/tags`, which new pages can't be created with as they
+/// would never be reachable
+const RESERVED_NAMES: [&str; 3] = ["pages", "tags", "reports"];
+
+/// Maximum depth of nested `<include-page>` elements
+pub(crate) const MAX_INCLUDE_DEPTH: usize = 5;
+
+// Serialises writes to the pages so that checking for conflicting changes and writing the file
+// happen together
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
 #[derive(Debug)]
 pub struct NotLoaded;
 
@@ -29,18 +46,99 @@ pub struct Loaded {
 pub struct Page<T: Debug> {
     pub name: String,
     path: PathBuf,
+    format: Format,
     meta: fs::Metadata,
     content: T,
 }
 
+/// The markup language a page is written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Org,
+    Text,
+}
+
+impl Format {
+    /// Supported formats in order of preference when more than one file has the same name
+    const ALL: [Format; 3] = [Format::Markdown, Format::Org, Format::Text];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Org => "org",
+            Format::Text => "txt",
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?;
+        Format::ALL
+            .into_iter()
+            .find(|format| extension == format.extension())
+    }
+}
+
+/// Who a list of pages is shown to. Private pages are only listed for authenticated users, and
+/// pages restricted to groups only for members of those groups.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Audience<'a> {
+    pub private_tags: &'a [String],
+    pub authenticated: bool,
+    pub groups: &'a [String],
+}
+
+impl<'a> Audience<'a> {
+    pub(crate) fn new(settings: &'a Settings, authenticated: bool, groups: &'a [String]) -> Self {
+        Audience {
+            private_tags: &settings.auth.private_tags,
+            authenticated,
+            groups,
+        }
+    }
+
+    /// Anyone at all. Used for content that is shared between users, like cached pages and
+    /// the sitemap.
+    pub(crate) fn anonymous(settings: &'a Settings) -> Self {
+        Self::new(settings, false, &[])
+    }
+}
+
+/// The source of a Markdown page that has not been saved, E.g. while it is being edited
+pub(crate) struct Draft<'a> {
+    metadata: Metadata,
+    body: &'a str,
+}
+
+/// Why a page could not be saved
+#[derive(Debug)]
+pub(crate) enum SaveError {
+    /// The page was modified after it was loaded for editing
+    Conflict,
+    /// A new page has the same slug as an existing page
+    Exists,
+    /// A new page name is empty or reserved, with the reason
+    InvalidName(&'static str),
+    Io(io::Error),
+}
+
 #[derive(Default, Debug, Deserialize)]
 #[serde(default)]
 struct Metadata {
     title: Option<String>,
+    description: Option<String>,
     tags: Vec<String>,
     hidden: bool,
+    /// Only authenticated users can view the page
+    private: bool,
+    /// Only members of these groups can view the page
+    groups: Vec<String>,
+    /// Date the page should be reviewed by, E.g. 2024-06-30
+    review_by: Option<String>,
+    markdown: MarkdownOverrides,
 }
 
+const DATE_FORMAT: &[FormatItem] = format_description!("[year]-[month]-[day]");
 const MTIME_DATE_FORMAT: &[FormatItem] = format_description!("[day] [month repr:long] [year]");
 const MTIME_HUMAN_FORMAT: &[FormatItem] =
     format_description!("[day] [month repr:long] [year], [hour repr:12]:[minute] [period] UTC");
@@ -72,14 +170,17 @@ where
             .unwrap()
     }
 
-    pub fn last_modified(&self, basepath: &Path) -> SystemTime {
-        if self.name == "home" {
-            // Home page lists recently changed files, so is modified whenever any
-            // other page is modified.
-            Page::last_modified_page(basepath)
-        } else {
-            self.mtime()
-        }
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The canonical name of the page in URLs
+    pub fn slug(&self) -> String {
+        self.name.to_slug()
     }
 
     fn is_empty(&self) -> bool {
@@ -88,12 +189,16 @@ where
 
     pub(crate) fn load(self) -> io::Result<Page<Loaded>> {
         let content = fs::read_to_string(&self.path)?;
-        let metadata = metadata(&content)?;
+        let metadata = match self.format {
+            Format::Markdown | Format::Text => metadata(&content)?,
+            Format::Org => org_metadata(&content),
+        };
 
         let loaded = Loaded { content, metadata };
         Ok(Page {
             name: self.name,
             path: self.path,
+            format: self.format,
             meta: self.meta,
             content: loaded,
         })
@@ -103,39 +208,70 @@ where
 impl Page<NotLoaded> {
     pub(crate) fn new(name: &FileName, basepath: &Path) -> Option<Page<NotLoaded>> {
         let name = name.as_str()?;
-        let path = basepath.join(name).with_extension("md");
-        let meta = fs::metadata(&path).ok()?;
-        meta.is_file().then(|| Page {
-            name: name.to_string(),
-            path,
-            meta,
-            content: NotLoaded,
+        Format::ALL.into_iter().find_map(|format| {
+            let path = basepath.join(name).with_extension(format.extension());
+            let meta = fs::metadata(&path).ok()?;
+            meta.is_file().then(|| Page {
+                name: name.to_string(),
+                path,
+                format,
+                meta,
+                content: NotLoaded,
+            })
+        })
+    }
+
+    /// Find the page with the same slug as `name`. An exact match on the file name is preferred,
+    /// otherwise `/Postgres-Tips` finds `postgres-tips.md` and `/my-notes` finds `My Notes.md`.
+    pub(crate) fn find(name: &str, basepath: &Path) -> Option<Page<NotLoaded>> {
+        Page::new(FileName::new(name), basepath).or_else(|| {
+            let slug = name.to_slug();
+            if slug.is_empty() {
+                return None;
+            }
+            Self::names(basepath)
+                .iter()
+                .filter(|name| name.to_slug() == slug)
+                .find_map(|name| Page::new(FileName::new(name), basepath))
         })
     }
 
     pub(crate) fn all(basepath: &Path) -> Vec<Page<NotLoaded>> {
-        Self::page_files_in(basepath)
-            .into_iter()
-            .filter_map(|path| {
-                path.file_stem()
-                    .and_then(|stem| stem.to_str())
-                    .and_then(|name| Page::new(FileName::new(name), basepath)) // TODO: Log error to create page?
-            })
+        Self::names(basepath)
+            .iter()
+            .filter_map(|name| Page::new(FileName::new(name), basepath)) // TODO: Log error to create page?
             .filter(|page| !page.is_empty() /*|| page.is_hidden()*/)
             .collect()
     }
 
-    pub(crate) fn recently_modified(limit: usize, basepath: &Path) -> Vec<Page<Loaded>> {
+    pub(crate) fn recently_modified(
+        limit: usize,
+        basepath: &Path,
+        audience: &Audience,
+    ) -> Vec<Page<Loaded>> {
         let mut pages = Self::all(basepath);
         pages.sort_by(|a, b| b.mtime().cmp(&a.mtime()));
         pages
             .into_iter()
             .filter_map(|page| page.load().ok())
-            .filter(|page| !page.is_hidden())
+            .filter(|page| page.is_listed_for(audience))
             .take(limit)
             .collect()
     }
 
+    // Collect the names first so that pages with files in more than one format are only included
+    // once
+    fn names(basepath: &Path) -> BTreeSet<String> {
+        Self::page_files_in(basepath)
+            .into_iter()
+            .filter_map(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(ToString::to_string)
+            })
+            .collect()
+    }
+
     fn page_files_in(basepath: &Path) -> Vec<PathBuf> {
         Self::page_files_in_impl(basepath).unwrap_or_else(|_err| {
             error!("unable to retrieve page files in {}", basepath.display());
@@ -145,17 +281,55 @@ impl Page<NotLoaded> {
 
     fn page_files_in_impl(basepath: &Path) -> io::Result<Vec<PathBuf>> {
         let mut pages = Vec::new();
-        let md = OsStr::new("md");
         for entry in fs::read_dir(basepath)?.flatten() {
             let file_name = entry.file_name();
-            let file_name = Path::new(&file_name);
-            if file_name.extension() == Some(md) {
+            if Format::from_path(Path::new(&file_name)).is_some() {
                 pages.push(entry.path());
             }
         }
         Ok(pages)
     }
 
+    /// Create a Markdown page named after the slug of `title`, with front-matter containing the
+    /// title, tags, and `created` date. Existing pages are never replaced.
+    pub(crate) fn create(
+        title: &str,
+        tags: &[String],
+        body: &str,
+        created: Date,
+        basepath: &Path,
+    ) -> Result<Page<NotLoaded>, SaveError> {
+        let source = new_page_source(title, tags, body, created);
+        Page::create_from_source(&title.to_slug(), &source, basepath)
+    }
+
+    /// Create a new Markdown page named `name`, which must be a slug, with the complete source
+    pub(crate) fn create_from_source(
+        name: &str,
+        source: &str,
+        basepath: &Path,
+    ) -> Result<Page<NotLoaded>, SaveError> {
+        if name.is_empty() {
+            return Err(SaveError::InvalidName(
+                "needs at least one letter or number",
+            ));
+        }
+        if RESERVED_NAMES.contains(&name) {
+            return Err(SaveError::InvalidName("is used by the site"));
+        }
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        // Any page with the same slug would be reached instead of this one
+        if Page::find(name, basepath).is_some() {
+            return Err(SaveError::Exists);
+        }
+        let path = basepath
+            .join(name)
+            .with_extension(Format::Markdown.extension());
+        write_atomically(&path, source)?;
+        Page::new(FileName::new(name), basepath)
+            .ok_or_else(|| SaveError::Io(io::Error::from(io::ErrorKind::NotFound)))
+    }
+
     pub(crate) fn home(basepath: &Path) -> Option<Page<NotLoaded>> {
         Page::new(FileName::new("home"), basepath)
     }
@@ -170,6 +344,118 @@ impl Page<NotLoaded> {
 }
 
 impl Page<Loaded> {
+    pub fn last_modified(&self, settings: &Settings) -> SystemTime {
+        let modified = self.dependencies_modified(settings, &mut vec![self.name.clone()]);
+        if self.name == "home" {
+            // Home page lists recently changed files, so is modified whenever any
+            // other page is modified.
+            modified.max(Page::last_modified_page(&settings.pages_path))
+        } else {
+            modified
+        }
+    }
+
+    /// Names of the pages included by this page with `<include-page>`
+    pub(crate) fn includes(&self) -> Vec<&str> {
+        attribute_values(self.body(), "include-page", "name")
+    }
+
+    /// Names of the pages this page links to.
+    ///
+    /// Only site-relative links to pages are considered, E.g. `[Example](/example)` or
+    /// `<a href="/example">`. Links are returned as slugs so they can be compared with
+    /// `Page::slug`.
+    pub(crate) fn links(&self) -> Vec<String> {
+        let body = self.body();
+        let mut links = Vec::new();
+        for prefix in ["](/", "href=\"/", "href='/"] {
+            for (i, _) in body.match_indices(prefix) {
+                let rest = &body[i + prefix.len()..];
+                let end = rest
+                    .find(|c: char| {
+                        matches!(c, ')' | '"' | '\'' | '#' | '?' | '/') || c.is_whitespace()
+                    })
+                    .unwrap_or(rest.len());
+                let name = &rest[..end];
+                // Links to other routes, like /tags/example, are followed by a /
+                if !name.is_empty() && !rest[end..].starts_with('/') {
+                    links.push(name.to_slug());
+                }
+            }
+        }
+        links.sort();
+        links.dedup();
+        links
+    }
+
+    /// Paths of the data files loaded by this page with `<csv-table>`
+    pub(crate) fn data_files(&self) -> Vec<&str> {
+        attribute_values(self.body(), "csv-table", "src")
+    }
+
+    /// Paths of the attachments this page links to under `/files/`, relative to the attachments
+    /// directory. The dimensions of images are included in the rendered page.
+    pub(crate) fn attachments(&self) -> Vec<String> {
+        let body = self.body();
+        let mut paths = body
+            .match_indices("/files/")
+            .filter_map(|(i, prefix)| {
+                let rest = &body[i + prefix.len()..];
+                let end = rest
+                    .find(|c: char| {
+                        matches!(c, ')' | ']' | '"' | '\'' | '#' | '?') || c.is_whitespace()
+                    })
+                    .unwrap_or(rest.len());
+                RawStr::new(&rest[..end])
+                    .percent_decode()
+                    .ok()
+                    .map(|path| path.into_owned())
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    // Returns the most recent mtime of this page, the pages it includes, and the files it loads
+    // or shows
+    fn dependencies_modified(&self, settings: &Settings, seen: &mut Vec<String>) -> SystemTime {
+        let basepath = &settings.pages_path;
+        let data_files = self
+            .data_files()
+            .into_iter()
+            .filter_map(|path| resolve_path(basepath, path));
+        let attachments = self
+            .attachments()
+            .into_iter()
+            .filter_map(|path| resolve_path(settings.attachments(), &path));
+        let mut modified = data_files
+            .chain(attachments)
+            .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+            .fold(self.mtime(), SystemTime::max);
+        if self.body().contains("<recently-changed-list") {
+            modified = modified.max(Page::last_modified_page(basepath));
+        }
+        if seen.len() > MAX_INCLUDE_DEPTH {
+            return modified;
+        }
+
+        for name in self.includes() {
+            if seen.iter().any(|seen_name| seen_name == name) {
+                continue;
+            }
+            if let Some(page) =
+                Page::new(FileName::new(name), basepath).and_then(|page| page.load().ok())
+            {
+                seen.push(name.to_string());
+                modified = modified.max(page.dependencies_modified(settings, seen));
+                seen.pop();
+            }
+        }
+
+        modified
+    }
+
     pub(crate) fn title(&self) -> String {
         self.metadata()
             .title
@@ -178,15 +464,43 @@ impl Page<Loaded> {
             .unwrap_or_else(|| titlecase(&self.name))
     }
 
-    pub fn markdown(&self) -> &str {
-        if self.content().lines().next() == Some(YAML_BOUNDARY) {
-            let mut chunks = self.content().splitn(3, YAML_BOUNDARY);
-            let _ = chunks.next();
-            let _yaml = chunks.next();
-            chunks.next().unwrap_or_default()
-        } else {
+    pub(crate) fn description(&self) -> Option<&str> {
+        self.metadata().description.as_deref()
+    }
+
+    /// The content of the page, excluding any front-matter
+    pub fn body(&self) -> &str {
+        if self.format == Format::Org {
             self.content()
+        } else {
+            strip_front_matter(self.content())
+        }
+    }
+
+    /// The full text of the page file, including any front-matter
+    pub(crate) fn source(&self) -> &str {
+        self.content()
+    }
+
+    /// Replace the page file with `source`, unless it has been modified since `loaded_mtime`.
+    /// The file is replaced atomically so the page is never seen partially written.
+    pub(crate) fn save(&self, source: &str, loaded_mtime: SystemTime) -> Result<(), SaveError> {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        if fs::metadata(&self.path)?.modified()? != loaded_mtime {
+            return Err(SaveError::Conflict);
+        }
+        write_atomically(&self.path, source)?;
+        Ok(())
+    }
+
+    /// Remove the page, unless it was modified after `loaded_mtime`
+    pub(crate) fn delete(&self, loaded_mtime: SystemTime) -> Result<(), SaveError> {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        if fs::metadata(&self.path)?.modified()? != loaded_mtime {
+            return Err(SaveError::Conflict);
         }
+        fs::remove_file(&self.path)?;
+        Ok(())
     }
 
     pub(crate) fn tags(&self) -> &[String] {
@@ -197,6 +511,48 @@ impl Page<Loaded> {
         self.metadata().hidden
     }
 
+    pub(crate) fn groups(&self) -> &[String] {
+        &self.metadata().groups
+    }
+
+    /// Does viewing the page require authentication. Pages restricted to groups are private too.
+    pub(crate) fn is_private(&self, private_tags: &[String]) -> bool {
+        self.metadata().private
+            || !self.groups().is_empty()
+            || self.tags().iter().any(|tag| private_tags.contains(tag))
+    }
+
+    /// Can `audience` view the page, whether or not it is hidden
+    pub(crate) fn is_visible_to(&self, audience: &Audience) -> bool {
+        let groups = self.groups();
+        let is_member =
+            groups.is_empty() || groups.iter().any(|group| audience.groups.contains(group));
+        is_member && (audience.authenticated || !self.is_private(audience.private_tags))
+    }
+
+    /// Should the page appear in lists of pages shown to `audience`
+    pub(crate) fn is_listed_for(&self, audience: &Audience) -> bool {
+        !self.is_hidden() && self.is_visible_to(audience)
+    }
+
+    pub(crate) fn review_by(&self) -> Option<Date> {
+        let review_by = self.metadata().review_by.as_deref()?;
+        Date::parse(review_by.trim(), DATE_FORMAT)
+            .map_err(|err| {
+                warn!(
+                    "{}: invalid review_by date '{}': {}",
+                    self.name, review_by, err
+                )
+            })
+            .ok()
+    }
+
+    /// The Markdown options for this page: the site defaults with any front-matter overrides
+    /// applied.
+    pub(crate) fn markdown_options(&self, defaults: &MarkdownOptions) -> MarkdownOptions {
+        defaults.with_overrides(&self.metadata().markdown)
+    }
+
     fn metadata(&self) -> &Metadata {
         &self.content.metadata
     }
@@ -206,6 +562,96 @@ impl Page<Loaded> {
     }
 }
 
+impl<'a> Draft<'a> {
+    /// Fails if the front-matter is invalid
+    pub(crate) fn parse(source: &'a str) -> io::Result<Self> {
+        Ok(Draft {
+            metadata: metadata(source)?,
+            body: strip_front_matter(source),
+        })
+    }
+
+    pub(crate) fn body(&self) -> &str {
+        self.body
+    }
+
+    pub(crate) fn markdown_options(&self, defaults: &MarkdownOptions) -> MarkdownOptions {
+        defaults.with_overrides(&self.metadata.markdown)
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+fn new_page_source(title: &str, tags: &[String], body: &str, created: Date) -> String {
+    #[derive(Serialize)]
+    struct FrontMatter<'a> {
+        title: &'a str,
+        #[serde(skip_serializing_if = "<[String]>::is_empty")]
+        tags: &'a [String],
+        created: String,
+    }
+
+    let front_matter = FrontMatter {
+        title,
+        tags,
+        created: created.format(DATE_FORMAT).unwrap_or_default(),
+    };
+    // Serialising a struct of strings can't fail
+    let yaml = serde_yaml::to_string(&front_matter).unwrap_or_default();
+    format!("{}\n{}{}\n{}", YAML_BOUNDARY, yaml, YAML_BOUNDARY, body)
+}
+
+/// The source of a Markdown page with front-matter from JSON metadata. Fails if the metadata is
+/// not valid front-matter.
+pub(crate) fn page_source(metadata: &serde_json::Value, body: &str) -> io::Result<String> {
+    if metadata.is_null()
+        || metadata
+            .as_object()
+            .map_or(false, |object| object.is_empty())
+    {
+        return Ok(body.to_string());
+    }
+    Metadata::deserialize(metadata).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let yaml =
+        serde_yaml::to_string(metadata).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    Ok(format!(
+        "{}\n{}{}\n{}",
+        YAML_BOUNDARY, yaml, YAML_BOUNDARY, body
+    ))
+}
+
+fn strip_front_matter(content: &str) -> &str {
+    if content.lines().next() == Some(YAML_BOUNDARY) {
+        let mut chunks = content.splitn(3, YAML_BOUNDARY);
+        let _ = chunks.next();
+        let _yaml = chunks.next();
+        chunks.next().unwrap_or_default()
+    } else {
+        content
+    }
+}
+
+// Write to a temporary file next to `path` then rename it into place. The temporary file starts
+// with a dot and doesn't have a page extension so it is never mistaken for a page.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or("page");
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+    let result = fs::File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
 fn metadata(content: &str) -> io::Result<Metadata> {
     if content.lines().next() == Some(YAML_BOUNDARY) {
         let mut chunks = content.splitn(3, YAML_BOUNDARY);
@@ -219,3 +665,286 @@ fn metadata(content: &str) -> io::Result<Metadata> {
         Ok(Metadata::default())
     }
 }
+
+// Org files specify metadata with keywords like `#+TITLE: Example`
+fn org_metadata(content: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    for line in content.lines() {
+        let (keyword, value) = match line.strip_prefix("#+").and_then(|l| l.split_once(':')) {
+            Some((keyword, value)) => (keyword, value.trim()),
+            None => continue,
+        };
+        match keyword.to_ascii_lowercase().as_str() {
+            "title" => metadata.title = Some(value.to_string()),
+            "description" => metadata.description = Some(value.to_string()),
+            // Tags are written :like:this: but accept whitespace separation too
+            "filetags" => metadata.tags.extend(org_list(value, ':')),
+            "hidden" => metadata.hidden = org_flag(value),
+            "private" => metadata.private = org_flag(value),
+            "groups" => metadata.groups.extend(org_list(value, ',')),
+            "review_by" => metadata.review_by = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    metadata
+}
+
+// Values separated by `separator` or whitespace
+fn org_list(value: &str, separator: char) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(move |c: char| c == separator || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(ToString::to_string)
+}
+
+// Org uses `t` and `nil` for booleans
+fn org_flag(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "t" | "true" | "yes")
+}
+
+/// Resolve a path relative to `basepath`, refusing paths that could escape it.
+pub(crate) fn resolve_path(basepath: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| basepath.join(path))
+}
+
+/// Find the values of `attribute` on all `element` tags in the source text.
+///
+/// This is a lightweight scan of the raw page text, used to discover the dependencies of a page
+/// without rendering it.
+fn attribute_values<'a>(source: &'a str, element: &str, attribute: &str) -> Vec<&'a str> {
+    let open = format!("<{}", element);
+    let mut values = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let value = tag.match_indices(attribute).find_map(|(i, _)| {
+            let preceded_by_space = tag[..i].ends_with(|c: char| c.is_ascii_whitespace());
+            let after = tag[i + attribute.len()..].strip_prefix('=')?;
+            let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+            let after = &after[1..];
+            let len = after.find(quote)?;
+            preceded_by_space.then(|| &after[..len])
+        });
+        values.extend(value);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, pages_path, TempDir};
+
+    const ANONYMOUS: Audience = Audience {
+        private_tags: &[],
+        authenticated: false,
+        groups: &[],
+    };
+
+    #[test]
+    fn find_by_slug() {
+        let page = Page::find("Sample-Page", &pages_path()).unwrap();
+        assert_eq!(page.name, "sample-page");
+        assert_eq!(page.slug(), "sample-page");
+
+        let page = Page::find("sample_page", &pages_path()).unwrap();
+        assert_eq!(page.name, "sample-page");
+
+        assert!(Page::find("missing-page", &pages_path()).is_none());
+    }
+
+    #[test]
+    fn private_pages() {
+        let page = Page::find("private", &pages_path())
+            .unwrap()
+            .load()
+            .unwrap();
+        assert!(page.is_private(&[]));
+        assert!(!page.is_listed_for(&ANONYMOUS));
+        assert!(page.is_listed_for(&Audience {
+            private_tags: &[],
+            authenticated: true,
+            groups: &[],
+        }));
+
+        let page = Page::find("sample-page", &pages_path())
+            .unwrap()
+            .load()
+            .unwrap();
+        assert!(!page.is_private(&[]));
+        assert!(page.is_private(&[String::from("sample")]));
+    }
+
+    #[test]
+    fn group_pages() {
+        let page = Page::find("engineering", &pages_path())
+            .unwrap()
+            .load()
+            .unwrap();
+        assert!(page.is_private(&[]));
+        assert!(!page.is_visible_to(&ANONYMOUS));
+
+        fn member_of(groups: &[String]) -> Audience {
+            Audience {
+                private_tags: &[],
+                authenticated: true,
+                groups,
+            }
+        }
+        assert!(page.is_listed_for(&member_of(&[String::from("engineering")])));
+        assert!(!page.is_listed_for(&member_of(&[String::from("sales")])));
+        assert!(!page.is_listed_for(&member_of(&[])));
+    }
+
+    #[test]
+    fn org_keywords() {
+        let metadata = org_metadata(
+            "#+TITLE: Org page\n#+FILETAGS: :one:two:\n#+HIDDEN: t\n#+PRIVATE: nil\n\
+             #+GROUPS: engineering, sales\n* Heading\n",
+        );
+        assert_eq!(metadata.title.as_deref(), Some("Org page"));
+        assert_eq!(metadata.tags, ["one", "two"]);
+        assert!(metadata.hidden);
+        assert!(!metadata.private);
+        assert_eq!(metadata.groups, ["engineering", "sales"]);
+    }
+
+    #[test]
+    fn save_detects_conflicts() {
+        let dir = TempDir::new("save");
+        let basepath = dir.path();
+        fs::write(basepath.join("draft.md"), "First").unwrap();
+
+        let page = Page::find("draft", basepath).unwrap().load().unwrap();
+        let loaded_mtime = page.mtime();
+
+        // Someone else changes the page after it was loaded
+        let file = fs::File::options()
+            .write(true)
+            .open(basepath.join("draft.md"))
+            .unwrap();
+        file.set_len(0).unwrap();
+        (&file).write_all(b"Changed elsewhere").unwrap();
+        file.set_modified(loaded_mtime + std::time::Duration::from_secs(1))
+            .unwrap();
+        drop(file);
+
+        // Saving with the modification time from when the page was loaded is rejected
+        let result = page.save("Second", loaded_mtime);
+        assert!(matches!(result, Err(SaveError::Conflict)));
+        assert_eq!(
+            fs::read_to_string(basepath.join("draft.md")).unwrap(),
+            "Changed elsewhere"
+        );
+
+        // Saving the reloaded page succeeds
+        let page = Page::find("draft", basepath).unwrap().load().unwrap();
+        page.save("Third", page.mtime()).unwrap();
+        assert_eq!(
+            fs::read_to_string(basepath.join("draft.md")).unwrap(),
+            "Third"
+        );
+        // No temporary files are left behind
+        let names = Page::names(basepath);
+        assert_eq!(names.into_iter().collect::<Vec<_>>(), ["draft"]);
+    }
+
+    #[test]
+    fn attachments_are_dependencies() {
+        let dir = TempDir::new("attachments");
+        let mut settings = test_support::settings();
+        settings.pages_path = dir.path().to_path_buf();
+        fs::write(
+            dir.path().join("photos.md"),
+            "![Cat](/files/photos/my%20cat.png)\n<img src=\"/files/photos/dog.png\">",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("photos")).unwrap();
+        let image = fs::File::create(dir.path().join("photos/my cat.png")).unwrap();
+
+        let page = Page::find("photos", dir.path()).unwrap().load().unwrap();
+        assert_eq!(page.attachments(), ["photos/dog.png", "photos/my cat.png"]);
+
+        // Replacing the image changes the page
+        let replaced = page.mtime() + std::time::Duration::from_secs(60);
+        image.set_modified(replaced).unwrap();
+        assert_eq!(page.last_modified(&settings), replaced);
+    }
+
+    #[test]
+    fn parse_draft() {
+        let draft = Draft::parse("---\ntitle: Draft\nmarkdown:\n  table: true\n---\nBody").unwrap();
+        assert_eq!(draft.body(), "\nBody");
+        assert!(draft.markdown_options(&MarkdownOptions::default()).table);
+
+        assert!(Draft::parse("---\ntags: [unterminated\n---\nBody").is_err());
+        assert_eq!(
+            Draft::parse("No front-matter").unwrap().body(),
+            "No front-matter"
+        );
+    }
+
+    #[test]
+    fn create_page() {
+        let dir = TempDir::new("create");
+        let basepath = dir.path();
+        let created = Date::from_calendar_date(2024, time::Month::June, 30).unwrap();
+        let tags = [String::from("example")];
+
+        let page = Page::create("New: Page", &tags, "Body\n", created, basepath)
+            .unwrap()
+            .load()
+            .unwrap();
+        let again = Page::create("new page", &[], "", created, basepath);
+        let empty = Page::create("!!!", &[], "", created, basepath);
+
+        assert_eq!(page.name, "new-page");
+        assert_eq!(page.title(), "New: Page");
+        assert_eq!(page.tags(), tags);
+        assert!(page.source().contains("created: ") && page.source().contains("2024-06-30"));
+        assert_eq!(page.body(), "\nBody\n");
+        assert!(matches!(again, Err(SaveError::Exists)));
+        assert!(matches!(empty, Err(SaveError::InvalidName(_))));
+        let reserved = Page::create("Tags", &[], "", created, basepath);
+        assert!(matches!(reserved, Err(SaveError::InvalidName(_))));
+    }
+
+    #[test]
+    fn delete_page() {
+        let dir = TempDir::new("delete");
+        let basepath = dir.path();
+        fs::write(basepath.join("old.md"), "Old").unwrap();
+
+        let page = Page::find("old", basepath).unwrap().load().unwrap();
+        let stale = page.mtime() - std::time::Duration::from_secs(1);
+        let conflict = page.delete(stale);
+        let kept = Page::find("old", basepath).is_some();
+        page.delete(page.mtime()).unwrap();
+        let deleted = Page::find("old", basepath).is_none();
+
+        assert!(matches!(conflict, Err(SaveError::Conflict)));
+        assert!(kept);
+        assert!(deleted);
+    }
+
+    #[test]
+    fn page_source_from_json() {
+        let metadata = serde_json::json!({"title": "API", "tags": ["example"]});
+        let source = page_source(&metadata, "Body").unwrap();
+        let draft = Draft::parse(&source).unwrap();
+        assert_eq!(draft.metadata.title.as_deref(), Some("API"));
+        assert_eq!(draft.metadata.tags, ["example"]);
+        assert_eq!(draft.body(), "\nBody");
+
+        assert_eq!(page_source(&serde_json::json!({}), "Body").unwrap(), "Body");
+        assert!(page_source(&serde_json::json!({"tags": "not a list"}), "Body").is_err());
+        assert!(page_source(&serde_json::json!({"hidden": "yes"}), "Body").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Purging of changed pages from shared caches, like Varnish or a CDN
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::page::Page;
+use crate::settings::Settings;
+use crate::web;
+
+/// Sends PURGE or BAN requests for URLs to the configured caches
+pub(crate) struct Purger {
+    endpoints: Vec<String>,
+    method: &'static str,
+    host: String,
+}
+
+/// Detects changes to the pages by comparing their modification times between calls to
+/// `changed_paths`
+#[derive(Default)]
+pub(crate) struct ChangeDetector {
+    pages: Option<HashMap<String, Snapshot>>,
+}
+
+// The parts of a page that determine which URLs it appears on
+struct Snapshot {
+    mtime: SystemTime,
+    slug: String,
+    tags: Vec<String>,
+}
+
+/// Check the pages for changes every `purge_interval` seconds and purge the affected URLs from
+/// the caches
+pub(crate) fn watch(settings: &Settings) {
+    let purger = Purger::new(settings);
+    let basepath = settings.pages_path.clone();
+    let interval = Duration::from_secs(settings.cache.purge_interval.max(1));
+    thread::spawn(move || {
+        let mut detector = ChangeDetector::default();
+        loop {
+            for path in detector.changed_paths(basepath) {
+                purger.purge(&path);
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+impl Purger {
+    pub(crate) fn new(settings: &Settings) -> Self {
+        Purger {
+            endpoints: settings.cache.purge_endpoints.clone(),
+            method: settings.cache.purge_method(),
+            host: settings.domain.clone(),
+        }
+    }
+
+    /// Purge `path` from each of the caches. Failures are logged and otherwise ignored.
+    pub(crate) fn purge(&self, path: &str) {
+        for endpoint in &self.endpoints {
+            let url = format!("{}{}", endpoint.trim_end_matches('/'), path);
+            // Caches key their entries on the Host the site is served from
+            match ureq::request(self.method, &url)
+                .set("Host", &self.host)
+                .call()
+            {
+                Ok(_) => info!("{} {}", self.method, url),
+                Err(err) => warn!("unable to {} {}: {}", self.method, url, err),
+            }
+        }
+    }
+}
+
+impl ChangeDetector {
+    /// Paths of the URLs affected by pages added, modified, or removed since the last call. The
+    /// first call only records the state of the pages.
+    pub(crate) fn changed_paths(&mut self, basepath: &Path) -> BTreeSet<String> {
+        let mut previous = self.pages.take();
+        let is_first = previous.is_none();
+        let mut pages = HashMap::new();
+        let mut paths = BTreeSet::new();
+
+        for page in Page::all(basepath) {
+            let old = previous.as_mut().and_then(|pages| pages.remove(&page.name));
+            match old {
+                Some(old) if old.mtime == page.mtime() => {
+                    pages.insert(page.name, old);
+                }
+                old => {
+                    let page = match page.load() {
+                        Ok(page) => page,
+                        Err(_) => continue,
+                    };
+                    let snapshot = Snapshot {
+                        mtime: page.mtime(),
+                        slug: page.slug(),
+                        tags: page.tags().to_vec(),
+                    };
+                    if !is_first {
+                        // The page may have been removed from tags as well as added to them
+                        if let Some(old) = old {
+                            affected_paths(&old, &mut paths);
+                        }
+                        affected_paths(&snapshot, &mut paths);
+                    }
+                    pages.insert(page.name, snapshot);
+                }
+            }
+        }
+
+        // Anything left was removed
+        for removed in previous.into_iter().flat_map(HashMap::into_values) {
+            affected_paths(&removed, &mut paths);
+        }
+
+        if !paths.is_empty() {
+            paths.insert(uri!(web::home).to_string());
+            paths.insert(uri!(web::page::index).to_string());
+        }
+        self.pages = Some(pages);
+        paths
+    }
+}
+
+fn affected_paths(snapshot: &Snapshot, paths: &mut BTreeSet<String>) {
+    paths.insert(uri!(web::page::show(name = &snapshot.slug)).to_string());
+    for tag in &snapshot.tags {
+        paths.insert(uri!(web::tag::show(name = tag)).to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn detects_changes() {
+        let dir = TempDir::new("purge");
+        let basepath = dir.path();
+        fs::write(basepath.join("first.md"), "First").unwrap();
+
+        let mut detector = ChangeDetector::default();
+        assert!(detector.changed_paths(basepath).is_empty());
+        assert!(detector.changed_paths(basepath).is_empty());
+
+        fs::write(
+            basepath.join("Second Page.md"),
+            "---\ntags: [example]\n---\nSecond",
+        )
+        .unwrap();
+        fs::remove_file(basepath.join("first.md")).unwrap();
+        let paths = detector.changed_paths(basepath);
+
+        assert_eq!(
+            paths.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["/", "/first", "/pages", "/second-page", "/tags/example"]
+        );
+    }
+
+    #[test]
+    fn sends_purge_requests() {
+        // Stand-in for Varnish
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                lines.push(line.trim().to_ascii_lowercase());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            lines
+        });
+
+        let purger = Purger {
+            endpoints: vec![format!("http://{}/", addr)],
+            method: "PURGE",
+            host: String::from("example.com"),
+        };
+        purger.purge("/sample-page");
+
+        let lines = cache.join().unwrap();
+        assert_eq!(lines[0], "purge /sample-page http/1.1");
+        assert!(lines.contains(&String::from("host: example.com")));
+    }
+}
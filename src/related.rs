@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use crate::page::{Audience, Page};
+use crate::settings::Settings;
+use crate::string_ext::StringExt;
+
+/// Maximum number of related pages listed for a page
+const RELATED_LIMIT: usize = 5;
+
+/// Pages less similar than this are not suggested by `RelatedPages::similar`
+const MAX_DISSIMILARITY: f64 = 0.5;
+
+/// A page related to another page
+#[derive(Debug, Clone)]
+pub struct Related {
+    pub name: String,
+    pub title: String,
+    pub mtime: SystemTime,
+}
+
+/// Related pages for every page, and the titles of the pages for suggesting similar ones.
+///
+/// Computing the related pages requires loading every page so the result is cached and only
+/// recomputed when the pages change.
+#[derive(Default)]
+pub struct RelatedPages {
+    index: Mutex<Option<Arc<Index>>>,
+}
+
+struct Index {
+    version: Version,
+    related: HashMap<String, Vec<Related>>,
+    // Every listed page
+    pages: Vec<Related>,
+}
+
+// Used to detect changes to the pages without loading them. The names are included so that
+// renames, which keep the modification time, are noticed.
+#[derive(PartialEq, Eq)]
+struct Version {
+    pages: Vec<(String, SystemTime)>,
+}
+
+// The parts of a page used to determine relatedness
+struct Entry {
+    name: String,
+    title: String,
+    mtime: SystemTime,
+    tags: Vec<String>,
+    links: Vec<String>,
+}
+
+impl RelatedPages {
+    /// Returns the pages related to the page with the supplied name, most related first. Private
+    /// pages are never included as the same related pages are shown to every user.
+    pub fn for_page(&self, name: &str, settings: &Settings) -> Vec<Related> {
+        self.index(settings).related_to(name)
+    }
+
+    /// Pages with a name or title similar to `name`, most similar first. Like the related pages
+    /// only pages listed for everyone are included.
+    pub fn similar(&self, name: &str, limit: usize, settings: &Settings) -> Vec<Related> {
+        let requested = name.to_slug();
+        if requested.is_empty() {
+            return Vec::new();
+        }
+
+        let index = self.index(settings);
+        let mut scored = index
+            .pages
+            .iter()
+            .filter_map(|page| {
+                let score = dissimilarity(&requested, &page.name.to_slug())
+                    .min(dissimilarity(&requested, &page.title.to_slug()));
+                (score <= MAX_DISSIMILARITY).then(|| (score, page))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, page)| page.clone())
+            .collect()
+    }
+
+    fn index(&self, settings: &Settings) -> Arc<Index> {
+        let pages = Page::all(&settings.pages_path);
+        let mut version = Version {
+            pages: pages
+                .iter()
+                .map(|page| (page.name.clone(), page.mtime()))
+                .collect(),
+        };
+        version.pages.sort();
+
+        if let Some(index) = self
+            .lock()
+            .as_ref()
+            .filter(|index| index.version == version)
+        {
+            return Arc::clone(index);
+        }
+
+        // The index is built without holding the lock so that other page views aren't blocked
+        // while every page is loaded. Concurrent requests may each build it, which is harmless.
+        let mut entries = pages
+            .into_iter()
+            .filter_map(|page| page.load().ok())
+            .filter(|page| page.is_listed_for(&Audience::anonymous(settings)))
+            .map(|page| Entry {
+                title: page.title(),
+                mtime: page.mtime(),
+                tags: page.tags().to_vec(),
+                links: page.links(),
+                name: page.name,
+            })
+            .collect::<Vec<_>>();
+        // Only consider links to other visible pages
+        let slugs = entries
+            .iter()
+            .map(|entry| entry.name.to_slug())
+            .collect::<HashSet<_>>();
+        for entry in &mut entries {
+            entry.links.retain(|link| slugs.contains(link));
+        }
+        let index = Arc::new(Index {
+            version,
+            related: rank(&entries),
+            pages: entries.iter().map(Entry::to_related).collect(),
+        });
+        *self.lock() = Some(Arc::clone(&index));
+        index
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Arc<Index>>> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Index {
+    fn related_to(&self, name: &str) -> Vec<Related> {
+        self.related.get(name).cloned().unwrap_or_default()
+    }
+}
+
+impl Entry {
+    fn to_related(&self) -> Related {
+        Related {
+            name: self.name.clone(),
+            title: self.title.clone(),
+            mtime: self.mtime,
+        }
+    }
+}
+
+// Score each pair of pages by the tags and outbound links they share. Each shared tag or link is
+// weighted by its rarity, so that sharing an uncommon tag counts for more than a common one.
+fn rank(entries: &[Entry]) -> HashMap<String, Vec<Related>> {
+    let tag_weights = rarity(entries.iter().map(|entry| &entry.tags), entries.len());
+    let link_weights = rarity(entries.iter().map(|entry| &entry.links), entries.len());
+
+    entries
+        .iter()
+        .map(|entry| {
+            let mut scored = entries
+                .iter()
+                .filter(|other| other.name != entry.name)
+                .filter_map(|other| {
+                    let score = shared_weight(&entry.tags, &other.tags, &tag_weights)
+                        + shared_weight(&entry.links, &other.links, &link_weights);
+                    (score > 0.0).then(|| (score, other))
+                })
+                .collect::<Vec<_>>();
+            scored.sort_by(|(a_score, a), (b_score, b)| {
+                b_score
+                    .partial_cmp(a_score)
+                    .unwrap()
+                    .then_with(|| a.title.cmp(&b.title))
+            });
+            let related = scored
+                .into_iter()
+                .take(RELATED_LIMIT)
+                .map(|(_, other)| other.to_related())
+                .collect();
+            (entry.name.clone(), related)
+        })
+        .collect()
+}
+
+// Inverse document frequency of each value
+fn rarity<'a>(
+    values: impl Iterator<Item = &'a Vec<String>>,
+    total: usize,
+) -> HashMap<&'a str, f64> {
+    let mut counts = HashMap::new();
+    for values in values {
+        for value in values.iter().collect::<HashSet<_>>() {
+            *counts.entry(value.as_str()).or_insert(0usize) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(value, count)| (value, (1.0 + total as f64 / count as f64).ln()))
+        .collect()
+}
+
+fn shared_weight(a: &[String], b: &[String], weights: &HashMap<&str, f64>) -> f64 {
+    let a = a.iter().collect::<HashSet<_>>();
+    b.iter()
+        .collect::<HashSet<_>>()
+        .intersection(&a)
+        .map(|value| weights.get(value.as_str()).copied().unwrap_or_default())
+        .sum()
+}
+
+// Edit distance between the slugs relative to their length, 0 is identical. Slugs that contain
+// the other are considered more similar, E.g. postgres and postgres-vacuum.
+fn dissimilarity(requested: &str, candidate: &str) -> f64 {
+    let longest = requested
+        .chars()
+        .count()
+        .max(candidate.chars().count())
+        .max(1);
+    let distance = requested.edit_distance(candidate) as f64 / longest as f64;
+    if !candidate.is_empty() && (candidate.contains(requested) || requested.contains(candidate)) {
+        distance / 2.0
+    } else {
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn entry(name: &str, tags: &[&str], links: &[&str]) -> Entry {
+        Entry {
+            name: name.to_string(),
+            title: name.to_string(),
+            mtime: SystemTime::UNIX_EPOCH,
+            tags: tags.iter().map(ToString::to_string).collect(),
+            links: links.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn names(related: &[Related]) -> Vec<&str> {
+        related
+            .iter()
+            .map(|related| related.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn rare_tags_rank_higher() {
+        let entries = [
+            entry("postgres-vacuum", &["postgres", "database"], &[]),
+            entry("postgres-indexes", &["postgres", "database"], &[]),
+            entry("mysql", &["database"], &[]),
+            entry("sqlite", &["database"], &[]),
+            entry("rust", &["programming"], &[]),
+        ];
+        let related = rank(&entries);
+
+        assert_eq!(
+            names(&related["postgres-vacuum"]),
+            ["postgres-indexes", "mysql", "sqlite"]
+        );
+        assert!(related["rust"].is_empty());
+    }
+
+    #[test]
+    fn shared_links_count() {
+        let entries = [
+            entry("a", &[], &["target", "other"]),
+            entry("b", &[], &["target"]),
+            entry("c", &[], &["elsewhere"]),
+        ];
+        let related = rank(&entries);
+
+        assert_eq!(names(&related["a"]), ["b"]);
+        assert!(related["c"].is_empty());
+    }
+
+    #[test]
+    fn similar_pages() {
+        let settings = test_support::settings();
+        let related = RelatedPages::default();
+        let similar = related.similar("Sampel-Page", 5, &settings);
+        assert_eq!(names(&similar).first(), Some(&"sample-page"));
+
+        // Hidden pages are not suggested
+        let similar = related.similar("hidden", 5, &settings);
+        assert!(!names(&similar).contains(&"hidden"));
+
+        assert!(related.similar("zzzzzzzzzzzz", 5, &settings).is_empty());
+    }
+}
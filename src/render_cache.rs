@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+/// Least recently used cache of rendered page content.
+///
+/// Rendering a page runs comrak, syntax highlighting, and the custom element decorators, which
+/// takes tens of milliseconds for some pages. Entries are keyed on the page name and its last
+/// modified time, which includes the pages, data files, and images it depends on, so a stale
+/// entry is never returned.
+pub struct RenderCache {
+    /// Maximum size of the cached content in bytes
+    limit: usize,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Usage of the render cache since startup
+#[derive(Debug, Clone, Copy)]
+pub struct RenderCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub size: usize,
+    pub limit: usize,
+}
+
+impl RenderCacheStats {
+    pub fn size_kib(&self) -> usize {
+        self.size / 1024
+    }
+
+    pub fn limit_kib(&self) -> usize {
+        self.limit / 1024
+    }
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    size: usize,
+    // Incremented on every access to order the entries by use
+    clock: u64,
+}
+
+struct Entry {
+    last_modified: SystemTime,
+    content: String,
+    last_used: u64,
+}
+
+impl RenderCache {
+    pub fn new(limit: usize) -> Self {
+        RenderCache {
+            limit,
+            state: Mutex::new(State::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached content of the page if it has not been modified since it was cached,
+    /// otherwise calls `render` and caches the result.
+    pub fn get_or_render(
+        &self,
+        name: &str,
+        last_modified: SystemTime,
+        render: impl FnOnce() -> String,
+    ) -> String {
+        if let Some(content) = self.get(name, last_modified) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return content;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // Render without holding the lock so that other pages can be served in the meantime
+        let content = render();
+        self.insert(name, last_modified, content.clone());
+        content
+    }
+
+    pub fn stats(&self) -> RenderCacheStats {
+        let state = self.lock();
+        RenderCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            size: state.size,
+            limit: self.limit,
+        }
+    }
+
+    fn get(&self, name: &str, last_modified: SystemTime) -> Option<String> {
+        let mut state = self.lock();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(name)?;
+        if entry.last_modified != last_modified {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.content.clone())
+    }
+
+    // The state remains consistent if a thread panics while holding the lock as the entries and
+    // their total size are updated together
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn insert(&self, name: &str, last_modified: SystemTime, content: String) {
+        let size = name.len() + content.len();
+        if size > self.limit {
+            return;
+        }
+
+        let mut state = self.lock();
+        state.clock += 1;
+        let entry = Entry {
+            last_modified,
+            content,
+            last_used: state.clock,
+        };
+        if let Some(replaced) = state.entries.insert(name.to_string(), entry) {
+            state.size -= name.len() + replaced.content.len();
+        }
+        state.size += size;
+
+        while state.size > self.limit {
+            let oldest = match state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+            {
+                Some((name, _)) => name.clone(),
+                None => break,
+            };
+            if let Some(evicted) = state.entries.remove(&oldest) {
+                state.size -= oldest.len() + evicted.content.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = RenderCache::new(1024);
+        let mtime = SystemTime::UNIX_EPOCH;
+        assert_eq!(
+            cache.get_or_render("page", mtime, || "first".into()),
+            "first"
+        );
+        assert_eq!(
+            cache.get_or_render("page", mtime, || "second".into()),
+            "first"
+        );
+
+        // Modifying the page invalidates the entry
+        let mtime = mtime + Duration::from_secs(1);
+        assert_eq!(
+            cache.get_or_render("page", mtime, || "third".into()),
+            "third"
+        );
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.size, "page".len() + "third".len());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = RenderCache::new(30);
+        let mtime = SystemTime::UNIX_EPOCH;
+        cache.get_or_render("a", mtime, || "a".repeat(10));
+        cache.get_or_render("b", mtime, || "b".repeat(10));
+        cache.get_or_render("a", mtime, || unreachable!());
+        cache.get_or_render("c", mtime, || "c".repeat(10));
+
+        // b was used least recently so it was evicted to make room for c
+        assert_eq!(cache.get_or_render("b", mtime, || "new".into()), "new");
+        assert!(cache.stats().size <= 30);
+
+        // Content larger than the limit isn't cached at all
+        cache.get_or_render("d", mtime, || "d".repeat(31));
+        assert_eq!(cache.get_or_render("d", mtime, || "small".into()), "small");
+    }
+}
@@ -0,0 +1,157 @@
+//! Reports that help with maintaining the pages
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use time::{Date, OffsetDateTime};
+
+use crate::page::{Audience, Loaded, Page};
+
+/// Why a page is considered stale
+#[derive(Debug)]
+pub enum Staleness {
+    /// The page has not been modified within the stale period
+    NotModified,
+    /// The review by date of the page has passed
+    ReviewDue(Date),
+}
+
+/// Pages that have no tags and are not linked to from any other page
+pub(crate) fn orphans(basepath: &Path, audience: &Audience) -> Vec<Page<Loaded>> {
+    let pages = visible_pages(basepath, audience);
+    let linked = pages
+        .iter()
+        .flat_map(|page| {
+            page.links()
+                .into_iter()
+                .filter(move |link| *link != page.slug())
+        })
+        .collect::<HashSet<_>>();
+
+    pages
+        .into_iter()
+        .filter(|page| page.tags().is_empty() && !linked.contains(&page.slug()))
+        .collect()
+}
+
+/// Pages without any tags
+pub(crate) fn untagged(basepath: &Path, audience: &Audience) -> Vec<Page<Loaded>> {
+    visible_pages(basepath, audience)
+        .into_iter()
+        .filter(|page| page.tags().is_empty())
+        .collect()
+}
+
+/// Pages that have not been modified within `stale_after` of `now`, or are past their review by
+/// date
+pub(crate) fn stale(
+    basepath: &Path,
+    audience: &Audience,
+    stale_after: Duration,
+    now: SystemTime,
+) -> Vec<(Page<Loaded>, Staleness)> {
+    let today = OffsetDateTime::from(now).date();
+    let mut stale = visible_pages(basepath, audience)
+        .into_iter()
+        .filter_map(|page| match page.review_by() {
+            Some(review_by) if review_by <= today => Some((page, Staleness::ReviewDue(review_by))),
+            Some(_) => None,
+            None if page.mtime() + stale_after < now => Some((page, Staleness::NotModified)),
+            None => None,
+        })
+        .collect::<Vec<_>>();
+    // Oldest first
+    stale.sort_by(|(a, _), (b, _)| a.mtime().cmp(&b.mtime()));
+    stale
+}
+
+/// Page names that share a slug, grouped by slug. Only one page of each group can be reached from
+/// its canonical URL. Only pages visible to `audience` are included, or all pages for None.
+pub(crate) fn collisions(
+    basepath: &Path,
+    audience: Option<&Audience>,
+) -> Vec<(String, Vec<String>)> {
+    let mut by_slug = BTreeMap::<_, Vec<_>>::new();
+    // Hidden pages can still be reached by their slug so they are included
+    let pages = Page::all(basepath)
+        .into_iter()
+        .filter_map(|page| page.load().ok())
+        .filter(|page| audience.map_or(true, |audience| page.is_visible_to(audience)));
+    for page in pages {
+        by_slug.entry(page.slug()).or_default().push(page.name);
+    }
+    by_slug
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .collect()
+}
+
+// Pages listed for `audience` sorted by name, excluding the home page
+fn visible_pages(basepath: &Path, audience: &Audience) -> Vec<Page<Loaded>> {
+    let mut pages = Page::all(basepath);
+    pages.sort_by(|a, b| a.name.cmp(&b.name));
+    pages
+        .into_iter()
+        .filter(|page| page.name != "home")
+        .filter_map(|page| page.load().ok())
+        .filter(|page| page.is_listed_for(audience))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{pages_path, TempDir};
+
+    const ANONYMOUS: Audience = Audience {
+        private_tags: &[],
+        authenticated: false,
+        groups: &[],
+    };
+
+    fn names(pages: &[Page<Loaded>]) -> Vec<&str> {
+        pages.iter().map(|page| page.name.as_str()).collect()
+    }
+
+    #[test]
+    fn untagged_pages() {
+        let untagged = untagged(&pages_path(), &ANONYMOUS);
+        assert!(names(&untagged).contains(&"no-metadata"));
+        assert!(!names(&untagged).contains(&"sample-page"));
+        assert!(!names(&untagged).contains(&"hidden"));
+        assert!(!names(&untagged).contains(&"private"));
+        assert!(!names(&untagged).contains(&"engineering"));
+    }
+
+    #[test]
+    fn collisions_of_visible_pages() {
+        let dir = TempDir::new("collisions");
+        let basepath = dir.path();
+        for name in ["Notes.md", "notes.txt", "secret.txt"] {
+            std::fs::write(basepath.join(name), "Content").unwrap();
+        }
+        std::fs::write(basepath.join("Secret.md"), "---\nprivate: true\n---\n").unwrap();
+        let signed_in = Audience {
+            authenticated: true,
+            ..ANONYMOUS
+        };
+
+        let anonymous = collisions(basepath, Some(&ANONYMOUS));
+        let authenticated = collisions(basepath, Some(&signed_in));
+        let all = collisions(basepath, None);
+
+        assert_eq!(anonymous.len(), 1);
+        assert_eq!(anonymous[0].0, "notes");
+        assert_eq!(authenticated.len(), 2);
+        assert!(authenticated.iter().any(|(slug, _)| slug == "secret"));
+        assert_eq!(all, authenticated);
+    }
+
+    #[test]
+    fn stale_pages() {
+        let future = SystemTime::now() + Duration::from_secs(7200);
+        let stale = stale(&pages_path(), &ANONYMOUS, Duration::from_secs(3600), future);
+        assert!(stale.iter().any(|(page, _)| page.name == "sample-page"));
+    }
+}
@@ -1,6 +1,11 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use rocket::serde::Deserialize;
+use rocket::serde::{de, Deserialize, Deserializer};
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -13,4 +18,304 @@ pub struct Settings {
     pub domain: String,
     pub tagline: String,
     pub sentry_dsn: Option<String>,
+    /// Directory to serve attachments from, defaults to `pages_path`
+    pub attachments_path: Option<PathBuf>,
+    /// Directory to store resized images in, defaults to a directory in the system temp dir
+    pub image_cache_path: Option<PathBuf>,
+    /// Pages not modified for this many days are reported as stale, defaults to 365
+    pub stale_after_days: Option<u64>,
+    /// Maximum memory used to cache rendered pages in bytes, defaults to 32 MiB. 0 disables
+    /// the cache.
+    pub render_cache_bytes: Option<usize>,
+    #[serde(default)]
+    pub markdown: MarkdownOptions,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub security: SecuritySettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub git: GitSettings,
+}
+
+impl Settings {
+    pub fn attachments(&self) -> &Path {
+        self.attachments_path.as_deref().unwrap_or(&self.pages_path)
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_secs(self.stale_after_days.unwrap_or(365) * 24 * 60 * 60)
+    }
+
+    pub fn render_cache_size(&self) -> usize {
+        self.render_cache_bytes.unwrap_or(32 * 1024 * 1024)
+    }
+
+    pub fn image_cache(&self) -> PathBuf {
+        self.image_cache_path
+            .clone()
+            .unwrap_or_else(|| env::temp_dir().join("pkb-images"))
+    }
+}
+
+/// How long each class of response may be cached for, in seconds, and which caches to purge
+/// when pages change.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheSettings {
+    pub pages: u64,
+    pub indexes: u64,
+    pub tags: u64,
+    /// Only applies to shared caches, like Varnish or a CDN
+    pub sitemap: u64,
+    pub assets: u64,
+    /// Allow caches to serve a stale response for this many seconds while they revalidate it
+    pub stale_while_revalidate: Option<u64>,
+    /// Base URLs of caches that are sent a PURGE request for each URL affected by a change to
+    /// a page, E.g. `http://127.0.0.1:6081`
+    pub purge_endpoints: Vec<String>,
+    /// Send BAN requests instead of PURGE
+    pub ban: bool,
+    /// How often to check the pages for changes to purge
+    pub purge_interval: u64,
+}
+
+/// The classes of response with their own cache lifetime in `CacheSettings`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CacheClass {
+    Pages,
+    Indexes,
+    Tags,
+    Sitemap,
+    Assets,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            pages: 60,
+            indexes: 60,
+            tags: 60,
+            sitemap: 24 * 60 * 60,
+            assets: 60,
+            stale_while_revalidate: None,
+            purge_endpoints: Vec::new(),
+            ban: false,
+            purge_interval: 10,
+        }
+    }
+}
+
+impl CacheSettings {
+    pub fn max_age(&self, class: CacheClass) -> Duration {
+        let secs = match class {
+            CacheClass::Pages => self.pages,
+            CacheClass::Indexes => self.indexes,
+            CacheClass::Tags => self.tags,
+            CacheClass::Sitemap => self.sitemap,
+            CacheClass::Assets => self.assets,
+        };
+        Duration::from_secs(secs)
+    }
+
+    pub fn purge_method(&self) -> &'static str {
+        if self.ban {
+            "BAN"
+        } else {
+            "PURGE"
+        }
+    }
+}
+
+/// Security related response headers and HTML sanitising. Empty header values are not sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SecuritySettings {
+    pub content_security_policy: String,
+    pub referrer_policy: String,
+    pub permissions_policy: String,
+    /// Send Strict-Transport-Security with this max-age in seconds
+    pub hsts_max_age: Option<u64>,
+    pub hsts_include_subdomains: bool,
+    /// Sanitise raw HTML in Markdown and Org-mode pages against an allowlist instead of passing
+    /// it through
+    pub sanitize_html: bool,
+}
+
+impl Default for SecuritySettings {
+    fn default() -> Self {
+        SecuritySettings {
+            // Syntax highlighting and diagrams use inline styles
+            content_security_policy: String::from(
+                "default-src 'self'; img-src 'self' data: https:; style-src 'self' 'unsafe-inline'; \
+                 object-src 'none'; base-uri 'self'; frame-ancestors 'self'",
+            ),
+            referrer_policy: String::from("strict-origin-when-cross-origin"),
+            permissions_policy: String::from("camera=(), geolocation=(), microphone=()"),
+            hsts_max_age: None,
+            hsts_include_subdomains: false,
+            sanitize_html: false,
+        }
+    }
+}
+
+/// Users that can view private pages
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AuthSettings {
+    /// User names and their argon2 password hashes in PHC string format
+    pub users: HashMap<String, String>,
+    /// Pages with any of these tags are private, as well as pages with `private: true`
+    pub private_tags: Vec<String>,
+    /// Address ranges of reverse proxies trusted to authenticate users with the
+    /// `X-Forwarded-User` and `X-Forwarded-Groups` headers, E.g. `10.0.0.0/8`
+    pub trusted_proxies: Vec<IpRange>,
+    /// Names of API clients and the bearer tokens they use to change pages through the API
+    pub api_tokens: HashMap<String, String>,
+}
+
+/// Recording of changes made through the web in a git repository
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct GitSettings {
+    /// Commit every page saved through the web to the git repository containing `pages_path`,
+    /// which is created in `pages_path` if needed
+    pub commit: bool,
+}
+
+/// A range of IP addresses in CIDR notation, E.g. `192.168.1.0/24` or `fd00::/8`. A single
+/// address is a range with just that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack socket have IPv4-mapped IPv6 addresses
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => same_prefix(
+                u32::from(range).into(),
+                u32::from(ip).into(),
+                32 - self.prefix_len,
+            ),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                same_prefix(u128::from(range), u128::from(ip), 128 - self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+// Compare the addresses ignoring the `host_bits` lowest bits
+fn same_prefix(a: u128, b: u128, host_bits: u32) -> bool {
+    // checked_shr fails for a shift of all 128 bits, where every address matches
+    a.checked_shr(host_bits).unwrap_or(0) == b.checked_shr(host_bits).unwrap_or(0)
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|err| format!("invalid IP range '{}': {}", s, err))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u32>()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in IP range '{}'", s))?,
+            None => max_len,
+        };
+        Ok(IpRange { addr, prefix_len })
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Optional Markdown extensions. All are disabled by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MarkdownOptions {
+    pub table: bool,
+    pub strikethrough: bool,
+    pub tasklist: bool,
+    pub autolink: bool,
+    pub footnotes: bool,
+    pub description_lists: bool,
+    pub superscript: bool,
+    /// Render `$...$` and `$$...$$` TeX math as MathML
+    pub math: bool,
+}
+
+/// Per-page overrides of the site-wide `MarkdownOptions`, set in the page front-matter.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MarkdownOverrides {
+    pub table: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub tasklist: Option<bool>,
+    pub autolink: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub description_lists: Option<bool>,
+    pub superscript: Option<bool>,
+    pub math: Option<bool>,
+}
+
+impl MarkdownOptions {
+    pub fn with_overrides(self, overrides: &MarkdownOverrides) -> MarkdownOptions {
+        MarkdownOptions {
+            table: overrides.table.unwrap_or(self.table),
+            strikethrough: overrides.strikethrough.unwrap_or(self.strikethrough),
+            tasklist: overrides.tasklist.unwrap_or(self.tasklist),
+            autolink: overrides.autolink.unwrap_or(self.autolink),
+            footnotes: overrides.footnotes.unwrap_or(self.footnotes),
+            description_lists: overrides
+                .description_lists
+                .unwrap_or(self.description_lists),
+            superscript: overrides.superscript.unwrap_or(self.superscript),
+            math: overrides.math.unwrap_or(self.math),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_ranges() {
+        let range = "10.1.0.0/16".parse::<IpRange>().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let range = "fd00::/8".parse::<IpRange>().unwrap();
+        assert!(range.contains("fd12::1".parse().unwrap()));
+        assert!(!range.contains("fe80::1".parse().unwrap()));
+
+        let single = "127.0.0.1".parse::<IpRange>().unwrap();
+        assert!(single.contains("127.0.0.1".parse().unwrap()));
+        assert!(!single.contains("127.0.0.2".parse().unwrap()));
+
+        let everything = "::/0".parse::<IpRange>().unwrap();
+        assert!(everything.contains("2001:db8::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com/8".parse::<IpRange>().is_err());
+    }
 }
@@ -5,6 +5,11 @@ pub trait StringExt {
     ///
     /// "This: is an example! 😬" → "this-is-an-example-grimacing"
     fn to_slug(&self) -> String;
+
+    /// Return the Levenshtein edit distance between this string and `other`, in characters.
+    ///
+    /// "kitten" → "sitting" = 3
+    fn edit_distance(&self, other: &str) -> usize;
 }
 
 impl<T> StringExt for T
@@ -73,6 +78,23 @@ where
 
         slug
     }
+
+    fn edit_distance(&self, other: &str) -> usize {
+        let a = self.as_ref().chars().collect::<Vec<_>>();
+        let mut previous = (0..=a.len()).collect::<Vec<_>>();
+        let mut current = vec![0; a.len() + 1];
+
+        for (j, b_char) in other.chars().enumerate() {
+            current[0] = j + 1;
+            for (i, &a_char) in a.iter().enumerate() {
+                let substitution = previous[i] + usize::from(a_char != b_char);
+                current[i + 1] = substitution.min(previous[i + 1] + 1).min(current[i] + 1);
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+
+        previous[a.len()]
+    }
 }
 
 fn add_word(slug: &mut String, word: &str) {
@@ -160,4 +182,14 @@ mod tests {
             "sea-water-at-60-slash-l-at-amazon"
         );
     }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!("kitten".edit_distance("sitting"), 3);
+        assert_eq!("".edit_distance("abc"), 3);
+        assert_eq!("abc".edit_distance(""), 3);
+        assert_eq!("same".edit_distance("same"), 0);
+        assert_eq!("postgress-vacuum".edit_distance("postgres-vacuum"), 1);
+        assert_eq!("étude".edit_distance("etude"), 1);
+    }
 }
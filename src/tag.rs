@@ -3,7 +3,7 @@ use std::path::Path;
 use std::rc::Rc;
 use std::time::SystemTime;
 
-use crate::page::{Loaded, Page};
+use crate::page::{Audience, Loaded, Page};
 
 pub type Pages = Vec<Rc<Page<Loaded>>>;
 
@@ -18,12 +18,13 @@ impl Tag {
         Tag { name, pages }
     }
 
-    pub(crate) fn all(basepath: &Path) -> Vec<Tag> {
+    /// Tags of the pages listed for `audience`
+    pub(crate) fn all(basepath: &Path, audience: &Audience) -> Vec<Tag> {
         let mappings = Page::all(basepath)
             .into_iter()
             .filter_map(|page| page.load().ok())
             .filter_map(|page| {
-                if page.is_hidden() {
+                if !page.is_listed_for(audience) {
                     None
                 } else {
                     Some(Rc::new(page))
@@ -52,8 +53,10 @@ impl Tag {
             .collect()
     }
 
-    pub fn find(name: &str, basepath: &Path) -> Option<Tag> {
-        Tag::all(basepath).into_iter().find(|tag| tag.name == name)
+    pub(crate) fn find(name: &str, basepath: &Path, audience: &Audience) -> Option<Tag> {
+        Tag::all(basepath, audience)
+            .into_iter()
+            .find(|tag| tag.name == name)
     }
 
     pub fn page_count(&self) -> usize {
@@ -1,25 +1,122 @@
 mod decorators;
+pub(crate) mod edit;
+pub(crate) mod error;
 mod layout;
+mod math;
 pub(crate) mod page;
+pub(crate) mod report;
+mod sanitize;
 pub(crate) mod tag;
 
 use comrak::plugins::syntect::SyntectAdapter;
 
+use crate::page::{Draft, Format, Loaded, Page};
+use crate::settings::{MarkdownOptions, Settings};
+
 pub use layout::{Layout, Nil};
 
+// Render a page to HTML according to its format. For Markdown the site options and any page
+// overrides are used. The HTML is sanitised, if configured, by `decorators::enhance_markup`.
+fn page_html(page: &Page<Loaded>, settings: &Settings, adapter: &SyntectAdapter) -> String {
+    match page.format() {
+        Format::Markdown => markdown(
+            page.body(),
+            &page.markdown_options(&settings.markdown),
+            adapter,
+        ),
+        Format::Org => org(page.body()),
+        Format::Text => plain_text(page.body()),
+    }
+}
+
+// Render a draft of a Markdown page that hasn't been saved
+fn draft_html(draft: &Draft, settings: &Settings, adapter: &SyntectAdapter) -> String {
+    markdown(
+        draft.body(),
+        &draft.markdown_options(&settings.markdown),
+        adapter,
+    )
+}
+
 // Render markdown to HTML
-fn markdown(v: &str, adapter: &SyntectAdapter) -> String {
+fn markdown(v: &str, markdown_options: &MarkdownOptions, adapter: &SyntectAdapter) -> String {
     use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+    let escaped;
+    let v = if markdown_options.math {
+        escaped = math::escape_math(v);
+        escaped.as_str()
+    } else {
+        v
+    };
     let mut options = ComrakOptions::default();
     options.render.unsafe_ = true; // Allow raw HTML
+    options.render.full_info_string = true; // Expose fence options, like `csv header`
+    options.extension.table = markdown_options.table;
+    options.extension.strikethrough = markdown_options.strikethrough;
+    options.extension.tasklist = markdown_options.tasklist;
+    options.extension.autolink = markdown_options.autolink;
+    options.extension.footnotes = markdown_options.footnotes;
+    options.extension.description_lists = markdown_options.description_lists;
+    options.extension.superscript = markdown_options.superscript;
     let mut plugins = ComrakPlugins::default();
     plugins.render.codefence_syntax_highlighter = Some(adapter);
     markdown_to_html_with_plugins(v, &options, &plugins)
 }
 
+// Render Org-mode to HTML
+fn org(v: &str) -> String {
+    let mut html = Vec::new();
+    match orgize::Org::parse(v).write_html(&mut html) {
+        Ok(()) => {
+            let html = String::from_utf8_lossy(&html);
+            // orgize wraps the document in <main>, which isn't appropriate within the page layout
+            html.strip_prefix("<main>")
+                .and_then(|html| html.strip_suffix("</main>"))
+                .unwrap_or(&html)
+                .to_string()
+        }
+        Err(err) => {
+            error!("unable to render Org-mode to HTML: {}", err);
+            String::new()
+        }
+    }
+}
+
+// Render plain text to HTML, treating blank lines as paragraph breaks
+fn plain_text(v: &str) -> String {
+    let v = v.replace("\r\n", "\n");
+    let mut html = String::with_capacity(v.len());
+    for paragraph in v.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        html.push_str("<p>");
+        for (i, line) in paragraph.lines().enumerate() {
+            if i > 0 {
+                html.push_str("<br>\n");
+            }
+            escape_html(line, &mut html);
+        }
+        html.push_str("</p>\n");
+    }
+    html
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::MarkdownOverrides;
+    use crate::test_support;
+    use kuchiki::traits::TendrilSink;
 
     // This isn't so much a test but documentation that comrak wraps the custom elements in a <p>
     // tag.
@@ -27,7 +124,94 @@ mod tests {
     fn test_custom_elements() {
         let md = "## Recently Updated Pages\n\n<recently-changed-list></recently-changed-list>\n";
         let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
-        let html = markdown(md, &adapter);
+        let html = markdown(md, &MarkdownOptions::default(), &adapter);
         assert_eq!(html, "<h2>Recently Updated Pages</h2>\n<p><recently-changed-list></recently-changed-list></p>\n")
     }
+
+    #[test]
+    fn test_extensions() {
+        let md = "| a | b |\n|---|---|\n| 1 | 2 |\n\n~~struck~~\n";
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+
+        let html = markdown(md, &MarkdownOptions::default(), &adapter);
+        assert!(!html.contains("<table>"));
+        assert!(!html.contains("<del>"));
+
+        let options = MarkdownOptions {
+            table: true,
+            strikethrough: true,
+            ..Default::default()
+        };
+        let html = markdown(md, &options, &adapter);
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<del>struck</del>"));
+    }
+
+    #[test]
+    fn test_org() {
+        let html = org("#+TITLE: Org Page\n\n* Heading\nSome /emphasis/.\n");
+        assert!(html.contains("<h1>Heading</h1>"));
+        assert!(html.contains("<i>emphasis</i>"));
+        assert!(!html.contains("<main>"));
+        assert!(!html.contains("Org Page"));
+    }
+
+    #[test]
+    fn test_sanitized_org() {
+        let mut settings = test_support::settings();
+        settings.security.sanitize_html = true;
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let source = "Before\n\n#+BEGIN_EXPORT html\n<script>alert(1)</script>\n#+END_EXPORT\n";
+        assert!(org(source).contains("<script>"));
+
+        let html = decorators::enhance_markup(&org(source), "test", &settings, &adapter);
+        assert!(html.contains("Before"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("alert(1)"));
+    }
+
+    #[test]
+    fn test_sanitized_math() {
+        let mut settings = test_support::settings();
+        settings.security.sanitize_html = true;
+        settings.markdown.math = true;
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let draft = Draft::parse("$\\text{<img src=x onerror=alert(1)>}$ and $x^2$\n").unwrap();
+
+        // The TeX is inserted by the decorators so is sanitised along with the rest of the page
+        let html = page::render_draft(&draft, &settings, &adapter);
+        let doc = kuchiki::parse_html().one(html.as_str());
+        assert!(doc.select("[onerror]").unwrap().next().is_none());
+        assert!(doc.select("math msup").unwrap().next().is_some());
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let html = plain_text("First line\nsecond <line>\n\n\nNext paragraph\n");
+        assert_eq!(
+            html,
+            "<p>First line<br>\nsecond &lt;line&gt;</p>\n<p>Next paragraph</p>\n"
+        );
+        assert_eq!(
+            plain_text("First\r\n\r\nSecond\r\n"),
+            "<p>First</p>\n<p>Second</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_extension_overrides() {
+        let options = MarkdownOptions {
+            table: true,
+            ..Default::default()
+        };
+        let overrides = MarkdownOverrides {
+            table: Some(false),
+            footnotes: Some(true),
+            ..Default::default()
+        };
+        let options = options.with_overrides(&overrides);
+        assert!(!options.table);
+        assert!(options.footnotes);
+        assert!(!options.strikethrough);
+    }
 }
@@ -1,38 +1,109 @@
+use std::ffi::OsStr;
+use std::fs;
 use std::path::Path;
 
+use comrak::plugins::syntect::SyntectAdapter;
 use html5ever::{LocalName, Namespace, QualName};
 use kuchiki::traits::TendrilSink;
 use kuchiki::{parse_fragment, Attribute, ExpandedName, NodeRef};
+use rocket::fs::FileName;
+use rocket::http::RawStr;
 
-use crate::page::Page;
+use crate::attachment::{self, RESPONSIVE_WIDTHS};
+use crate::page::{resolve_path, Audience, Page, MAX_INCLUDE_DEPTH};
 use crate::settings::Settings;
-use crate::web;
+use crate::templates::math::{self, MATH_ELEMENT};
+use crate::templates::sanitize;
+use crate::{templates, web};
 
 use crate::string_ext::StringExt;
 
 const START_HTML: &str = "<html>";
 const END_HTML: &str = "</html>";
 
-// Enhance the HTML
-pub fn enhance_markup(html: &str, settings: &Settings) -> String {
+// Enhance the HTML of the page with the supplied name. When the site is configured to sanitise
+// HTML the enhanced HTML is sanitised, as the decorators insert content from the pages too, such
+// as the TeX of math.
+pub fn enhance_markup(
+    html: &str,
+    name: &str,
+    settings: &Settings,
+    adapter: &SyntectAdapter,
+) -> String {
     let doc = parse_markup(html);
-
-    link_headings(&doc);
-    process_custom_elements(&doc, &settings.pages_path);
+    enhance(&doc, &mut vec![name.to_string()], settings, adapter);
+    let doc = if settings.security.sanitize_html {
+        parse_markup(&sanitize::sanitize(&serialize(&doc)))
+    } else {
+        doc
+    };
+    // Diagrams are rendered after sanitising as their SVG is styled by a <style> element, which
+    // the sanitiser would remove. svgbob escapes the text of the diagram.
+    render_diagrams(&doc);
     trim_pre_whitespace(&doc);
+    serialize(&doc)
+}
 
-    let mut enhanced_html = doc.to_string();
+// Serialise a document returned by `parse_markup`
+fn serialize(doc: &NodeRef) -> String {
+    let mut html = doc.to_string();
     // HACK: The document ends up serialised with a wrapping `<html>` element around the content
     // strip that here. E.g.
     // https://github.com/kuchiki-rs/kuchiki/blob/f652e38b12cb0d33f7bb0565b6933a6e2823a0c5/src/tests.rs#L66
-    if enhanced_html.ends_with(END_HTML) {
-        enhanced_html.truncate(enhanced_html.len() - END_HTML.len());
+    if html.ends_with(END_HTML) {
+        html.truncate(html.len() - END_HTML.len());
     }
-    if enhanced_html.starts_with(START_HTML) {
-        enhanced_html[START_HTML.len()..].to_string()
+    if html.starts_with(START_HTML) {
+        html[START_HTML.len()..].to_string()
     } else {
-        enhanced_html
+        html
+    }
+}
+
+// `includes` is the stack of pages being rendered, used to detect include cycles. Diagrams and
+// code blocks are finished by `enhance_markup` once the included pages are in place.
+fn enhance(
+    doc: &NodeRef,
+    includes: &mut Vec<String>,
+    settings: &Settings,
+    adapter: &SyntectAdapter,
+) {
+    link_headings(doc);
+    callouts(doc);
+    process_custom_elements(doc, includes, settings, adapter);
+    render_csv_blocks(doc);
+    responsive_images(doc, settings);
+}
+
+/// Longest summary returned by `summary`
+const SUMMARY_LENGTH: usize = 200;
+
+/// Returns the text of the first paragraph of the HTML, truncated to a reasonable length
+pub fn summary(html: &str) -> Option<String> {
+    let doc = parse_markup(html);
+    let p = doc.select_first("p").ok()?;
+    let text = p.text_contents();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    if text.chars().count() <= SUMMARY_LENGTH {
+        return Some(text);
+    }
+
+    // Truncate at a word boundary
+    let mut summary = String::with_capacity(SUMMARY_LENGTH + 3);
+    for word in text.split(' ') {
+        if summary.chars().count() + word.chars().count() + 1 > SUMMARY_LENGTH {
+            break;
+        }
+        if !summary.is_empty() {
+            summary.push(' ');
+        }
+        summary.push_str(word);
     }
+    summary.push('…');
+    Some(summary)
 }
 
 fn link_headings(doc: &NodeRef) {
@@ -58,8 +129,346 @@ fn link_headings(doc: &NodeRef) {
     }
 }
 
-fn process_custom_elements(doc: &NodeRef, basepath: &Path) {
-    RecentlyChangedList::process(doc, basepath);
+struct CalloutKind {
+    marker: &'static str,
+    class: &'static str,
+    title: &'static str,
+    icon: &'static str,
+}
+
+const CALLOUT_KINDS: &[CalloutKind] = &[
+    CalloutKind {
+        marker: "NOTE",
+        class: "note",
+        title: "Note",
+        icon: "ℹ",
+    },
+    CalloutKind {
+        marker: "TIP",
+        class: "tip",
+        title: "Tip",
+        icon: "💡",
+    },
+    CalloutKind {
+        marker: "IMPORTANT",
+        class: "important",
+        title: "Important",
+        icon: "❗",
+    },
+    CalloutKind {
+        marker: "WARNING",
+        class: "warning",
+        title: "Warning",
+        icon: "⚠",
+    },
+    CalloutKind {
+        marker: "CAUTION",
+        class: "caution",
+        title: "Caution",
+        icon: "⛔",
+    },
+];
+
+// Turn GitHub style `> [!NOTE]` blockquotes into callout asides
+fn callouts(doc: &NodeRef) {
+    let p_qual = QualName::new(None, ns!(html), local_name!("p"));
+    let quotes = doc.select("blockquote").unwrap().collect::<Vec<_>>();
+    for quote in quotes {
+        let quote = quote.as_node();
+        let p = match quote.children().find(|node| node.as_element().is_some()) {
+            Some(p) if p.as_element().map_or(false, |e| e.name == p_qual) => p,
+            Some(_) | None => continue,
+        };
+        let text = match p.first_child() {
+            Some(text) if text.as_text().is_some() => text,
+            Some(_) | None => continue,
+        };
+        let contents = text.as_text().unwrap().borrow().clone();
+        let (kind, title, rest) = match parse_callout_marker(&contents) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        let aside = NodeRef::new_element(
+            el_name("aside"),
+            [attr("class", format!("callout {}", kind.class))],
+        );
+        let heading =
+            NodeRef::new_element(el_name("p"), [attr("class", String::from("callout-title"))]);
+        let icon = NodeRef::new_element(
+            el_name("span"),
+            [
+                attr("class", String::from("callout-icon")),
+                attr("aria-hidden", String::from("true")),
+            ],
+        );
+        icon.append(NodeRef::new_text(kind.icon));
+        heading.append(icon);
+        heading.append(NodeRef::new_text(format!(
+            " {}",
+            title.unwrap_or(kind.title)
+        )));
+        aside.append(heading);
+
+        // Remove the marker from the first paragraph, dropping it entirely if nothing is left
+        if rest.trim().is_empty() {
+            text.detach();
+        } else {
+            *text.as_text().unwrap().borrow_mut() = rest.to_string();
+        }
+        let p_is_empty = p.children().all(|node| {
+            node.as_text()
+                .map_or(false, |t| t.borrow().trim().is_empty())
+        });
+        if p_is_empty {
+            p.detach();
+        }
+
+        for child in quote.children().collect::<Vec<_>>() {
+            aside.append(child);
+        }
+        quote.insert_after(aside);
+        quote.detach();
+    }
+}
+
+// Parses `[!KIND] Optional title` from the start of the text, returning the kind, title and the
+// text following the marker line
+fn parse_callout_marker(text: &str) -> Option<(&'static CalloutKind, Option<&str>, &str)> {
+    let text = text.trim_start().strip_prefix("[!")?;
+    let end = text.find(']')?;
+    let marker = &text[..end];
+    let kind = CALLOUT_KINDS
+        .iter()
+        .find(|kind| kind.marker.eq_ignore_ascii_case(marker))?;
+
+    let text = &text[end + 1..];
+    let (line, rest) = text.split_once('\n').unwrap_or((text, ""));
+    let title = Some(line.trim()).filter(|title| !title.is_empty());
+    Some((kind, title, rest))
+}
+
+fn process_custom_elements(
+    doc: &NodeRef,
+    includes: &mut Vec<String>,
+    settings: &Settings,
+    adapter: &SyntectAdapter,
+) {
+    RecentlyChangedList::process(doc, settings);
+    IncludePage::process(doc, includes, settings, adapter);
+    Math::process(doc);
+    CsvTable::process(doc, settings);
+}
+
+// Replace svgbob fenced code blocks with inline SVG
+fn render_diagrams(doc: &NodeRef) {
+    let blocks = doc
+        .select("pre > code.language-svgbob, pre > code.language-bob")
+        .unwrap()
+        .collect::<Vec<_>>();
+    for code in blocks {
+        let pre = match code.as_node().parent() {
+            Some(pre) => pre,
+            None => continue,
+        };
+
+        // Use the current text colour so that diagrams match the surrounding content
+        let svgbob_settings = svgbob::Settings {
+            stroke_color: String::from("currentColor"),
+            fill_color: String::from("currentColor"),
+            background: String::from("transparent"),
+            include_backdrop: false,
+            ..Default::default()
+        };
+        let svg = svgbob::to_svg_with_settings(&code.text_contents(), &svgbob_settings);
+
+        let figure =
+            NodeRef::new_element(el_name("figure"), [attr("class", String::from("diagram"))]);
+        for node in fragment_nodes(&parse_markup(&svg)) {
+            figure.append(node);
+        }
+        pre.insert_after(figure);
+        pre.detach();
+    }
+}
+
+// Replace csv and tsv fenced code blocks with tables. A header row is used when the fence
+// includes the `header` option, E.g. ```csv header
+fn render_csv_blocks(doc: &NodeRef) {
+    // Blocks in included pages have already been rendered, with the source moved into a
+    // details.csv-source element, when the including page is enhanced
+    let blocks = doc
+        .select("pre > code.language-csv, pre > code.language-tsv")
+        .unwrap()
+        .filter(|code| !code.as_node().ancestors().any(|node| is_csv_source(&node)))
+        .collect::<Vec<_>>();
+    for code in blocks {
+        let pre = match code.as_node().parent() {
+            Some(pre) => pre,
+            None => continue,
+        };
+        let (delimiter, header) = {
+            let attributes = code.attributes.borrow();
+            let delimiter = if attributes.get("class") == Some("language-tsv") {
+                b'\t'
+            } else {
+                b','
+            };
+            let header = attributes.get("data-meta").map_or(false, |meta| {
+                meta.split_whitespace().any(|opt| opt == "header")
+            });
+            (delimiter, header)
+        };
+
+        match csv_table(&code.text_contents(), delimiter, header) {
+            Ok(table) => {
+                // Keep the source available behind a disclosure element
+                let details = NodeRef::new_element(
+                    el_name("details"),
+                    [attr("class", String::from("csv-source"))],
+                );
+                let summary = NodeRef::new_element(el_name("summary"), []);
+                summary.append(NodeRef::new_text("View source"));
+                details.append(summary);
+                pre.insert_after(table.clone());
+                table.insert_after(details.clone());
+                details.append(pre);
+            }
+            Err(err) => warn!("unable to render CSV block: {}", err),
+        }
+    }
+}
+
+fn is_csv_source(node: &NodeRef) -> bool {
+    node.as_element().map_or(false, |element| {
+        &*element.name.local == "details"
+            && element.attributes.borrow().get("class") == Some("csv-source")
+    })
+}
+
+// Build a table from CSV data. Columns where every value is a number are given the `numeric`
+// class so that they can be aligned.
+fn csv_table(data: &str, delimiter: u8, header: bool) -> Result<NodeRef, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(data.as_bytes());
+    let mut rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+    let head = if header && !rows.is_empty() {
+        Some(rows.remove(0))
+    } else {
+        None
+    };
+
+    let columns = rows.iter().chain(&head).map(|row| row.len()).max();
+    let numeric = (0..columns.unwrap_or(0))
+        .map(|i| {
+            let mut values = rows
+                .iter()
+                .filter_map(|row| row.get(i))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .peekable();
+            values.peek().is_some() && values.all(|value| value.parse::<f64>().is_ok())
+        })
+        .collect::<Vec<_>>();
+
+    let table = NodeRef::new_element(el_name("table"), [attr("class", String::from("csv"))]);
+    if let Some(head) = head {
+        let thead = NodeRef::new_element(el_name("thead"), []);
+        let tr = NodeRef::new_element(el_name("tr"), []);
+        for (i, value) in head.iter().enumerate() {
+            tr.append(table_cell("th", value, numeric.get(i) == Some(&true)));
+        }
+        thead.append(tr);
+        table.append(thead);
+    }
+    let tbody = NodeRef::new_element(el_name("tbody"), []);
+    for row in &rows {
+        let tr = NodeRef::new_element(el_name("tr"), []);
+        for (i, value) in row.iter().enumerate() {
+            tr.append(table_cell("td", value, numeric[i]));
+        }
+        tbody.append(tr);
+    }
+    table.append(tbody);
+
+    Ok(table)
+}
+
+fn table_cell(name: &str, value: &str, numeric: bool) -> NodeRef {
+    let attrs = if numeric {
+        vec![attr("class", String::from("numeric"))]
+    } else {
+        Vec::new()
+    };
+    let cell = NodeRef::new_element(el_name(name), attrs);
+    cell.append(NodeRef::new_text(value.trim()));
+    cell
+}
+
+// Images are at most this wide on the page
+const IMAGE_SIZES: &str = "(max-width: 650px) 100vw, 650px";
+
+// Add dimensions, lazy loading, and a srcset of resized variants to images from the attachments
+// directory
+fn responsive_images(doc: &NodeRef, settings: &Settings) {
+    for img in doc.select("img").unwrap() {
+        let mut attributes = img.attributes.borrow_mut();
+        let src = match attributes.get("src") {
+            Some(src) => src.to_string(),
+            None => continue,
+        };
+        let encoded_path = match src.strip_prefix("/files/") {
+            Some(path) => path,
+            None => continue,
+        };
+        let full_path = match RawStr::new(encoded_path)
+            .percent_decode()
+            .ok()
+            .and_then(|path| resolve_path(settings.attachments(), &path))
+        {
+            Some(path) => path,
+            None => continue,
+        };
+        let (width, height) = match attachment::dimensions(&full_path) {
+            Ok(dimensions) => dimensions,
+            Err(err) => {
+                warn!(
+                    "unable to read dimensions of {}: {}",
+                    full_path.display(),
+                    err
+                );
+                continue;
+            }
+        };
+
+        if !attributes.contains("width") && !attributes.contains("height") {
+            attributes.insert("width", width.to_string());
+            attributes.insert("height", height.to_string());
+        }
+        if !attributes.contains("loading") {
+            attributes.insert("loading", String::from("lazy"));
+        }
+        if !attributes.contains("srcset") && attachment::is_resizable(&full_path) {
+            let mut srcset = RESPONSIVE_WIDTHS
+                .iter()
+                .filter(|&&variant_width| variant_width < width)
+                .map(|variant_width| {
+                    format!(
+                        "/resized/{}/{} {}w",
+                        variant_width, encoded_path, variant_width
+                    )
+                })
+                .collect::<Vec<_>>();
+            if !srcset.is_empty() {
+                srcset.push(format!("{} {}w", src, width));
+                attributes.insert("srcset", srcset.join(", "));
+                attributes.insert("sizes", String::from(IMAGE_SIZES));
+            }
+        }
+    }
 }
 
 fn trim_pre_whitespace(doc: &NodeRef) {
@@ -84,27 +493,23 @@ struct RecentlyChangedList;
 const RECENTLY_MODIFIED_LIMIT: usize = 5;
 
 impl RecentlyChangedList {
-    fn process(doc: &NodeRef, basepath: &Path) {
-        let p_qual = QualName::new(None, ns!(html), local_name!("p"));
+    fn process(doc: &NodeRef, settings: &Settings) {
         for elem in doc.select("recently-changed-list").unwrap() {
-            // comrak wraps the element in <p> tag so we need to replace it to avoid generating
-            // invalid markup
-            let parent = elem.as_node().parent();
-            let node_to_replace = match parent {
-                Some(ref parent) if parent.as_element().map_or(false, |e| e.name == p_qual) => {
-                    parent
-                }
-                Some(_) | None => elem.as_node(),
-            };
+            let node_to_replace = replaceable_node(elem.as_node());
 
             let list = NodeRef::new_element(el_name("ul"), []);
-            for page in Page::recently_modified(RECENTLY_MODIFIED_LIMIT, basepath) {
+            // Rendered pages are shared between users so never list private pages
+            for page in Page::recently_modified(
+                RECENTLY_MODIFIED_LIMIT,
+                &settings.pages_path,
+                &Audience::anonymous(settings),
+            ) {
                 let li = NodeRef::new_element(el_name("li"), []);
                 let link = NodeRef::new_element(
                     el_name("a"),
                     [attr(
                         "href",
-                        uri!(web::page::show(name = &page.name)).to_string(),
+                        uri!(web::page::show(name = &page.slug())).to_string(),
                     )],
                 );
                 link.append(NodeRef::new_text(page.title()));
@@ -130,6 +535,208 @@ impl RecentlyChangedList {
     }
 }
 
+struct IncludePage;
+
+impl IncludePage {
+    fn process(
+        doc: &NodeRef,
+        includes: &mut Vec<String>,
+        settings: &Settings,
+        adapter: &SyntectAdapter,
+    ) {
+        // Collect the elements up front as replacing them would otherwise stop the iteration
+        let elems = doc.select("include-page").unwrap().collect::<Vec<_>>();
+        for elem in elems {
+            let node_to_replace = replaceable_node(elem.as_node());
+            let name = elem
+                .attributes
+                .borrow()
+                .get("name")
+                .unwrap_or_default()
+                .to_string();
+
+            match Self::include(&name, includes, settings, adapter) {
+                Ok(fragment) => {
+                    for node in fragment_nodes(&fragment) {
+                        node_to_replace.insert_before(node);
+                    }
+                }
+                Err(message) => {
+                    warn!("unable to include page '{}': {}", name, message);
+                    let p = NodeRef::new_element(
+                        el_name("p"),
+                        [attr("class", String::from("include-error"))],
+                    );
+                    p.append(NodeRef::new_text(format!(
+                        "Unable to include page '{}': {}",
+                        name, message
+                    )));
+                    node_to_replace.insert_before(p);
+                }
+            }
+            node_to_replace.detach();
+        }
+    }
+
+    fn include(
+        name: &str,
+        includes: &mut Vec<String>,
+        settings: &Settings,
+        adapter: &SyntectAdapter,
+    ) -> Result<NodeRef, &'static str> {
+        if includes.iter().any(|included| included == name) {
+            return Err("include cycle detected");
+        }
+        if includes.len() > MAX_INCLUDE_DEPTH {
+            return Err("include depth limit reached");
+        }
+        let page = Page::new(FileName::new(name), &settings.pages_path)
+            .and_then(|page| page.load().ok())
+            .ok_or("page not found")?;
+        if page.is_private(&settings.auth.private_tags) {
+            return Err("page is private");
+        }
+
+        let doc = parse_markup(&templates::page_html(&page, settings, adapter));
+        includes.push(name.to_string());
+        enhance(&doc, includes, settings, adapter);
+        includes.pop();
+        Ok(doc)
+    }
+}
+
+/// Renders a table from a CSV or TSV file stored with the pages:
+/// `<csv-table src="data/file.csv" header></csv-table>`
+struct CsvTable;
+
+impl CsvTable {
+    fn process(doc: &NodeRef, settings: &Settings) {
+        let elems = doc.select("csv-table").unwrap().collect::<Vec<_>>();
+        for elem in elems {
+            let node_to_replace = replaceable_node(elem.as_node());
+            let (src, header) = {
+                let attributes = elem.attributes.borrow();
+                (
+                    attributes.get("src").unwrap_or_default().to_string(),
+                    attributes.contains("header"),
+                )
+            };
+
+            match Self::load(&src, header, settings) {
+                Ok(table) => node_to_replace.insert_before(table),
+                Err(message) => {
+                    warn!("unable to load CSV table '{}': {}", src, message);
+                    let p = NodeRef::new_element(
+                        el_name("p"),
+                        [attr("class", String::from("include-error"))],
+                    );
+                    p.append(NodeRef::new_text(format!(
+                        "Unable to load table '{}': {}",
+                        src, message
+                    )));
+                    node_to_replace.insert_before(p);
+                }
+            }
+            node_to_replace.detach();
+        }
+    }
+
+    // Only data files are read, so the source of pages can't be shown. Like /files, files in a
+    // folder named after a hidden or private page are not available, and rendered pages are
+    // shared by all viewers so that applies to authenticated users as well.
+    fn load(src: &str, header: bool, settings: &Settings) -> Result<NodeRef, String> {
+        let path = resolve_path(&settings.pages_path, src).ok_or("invalid path")?;
+        let delimiter = match path.extension().and_then(OsStr::to_str) {
+            Some("csv") => b',',
+            Some("tsv") => b'\t',
+            _ => return Err(String::from("not a CSV or TSV file")),
+        };
+        let audience = Audience::anonymous(settings);
+        let owner = attachment::owning_page(Path::new(src), &settings.pages_path);
+        if owner.map_or(false, |page| !page.is_listed_for(&audience)) {
+            return Err(String::from("file is private"));
+        }
+        let data = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        csv_table(&data, delimiter, header).map_err(|err| err.to_string())
+    }
+}
+
+struct Math;
+
+impl Math {
+    fn process(doc: &NodeRef) {
+        let elems = doc.select(MATH_ELEMENT).unwrap().collect::<Vec<_>>();
+        for elem in elems {
+            let (tex, display) = {
+                let attributes = elem.attributes.borrow();
+                (
+                    attributes.get("data-tex").unwrap_or_default().to_string(),
+                    attributes.get("display") == Some("block"),
+                )
+            };
+
+            // Display math on its own is wrapped in a <p> by comrak, which needs to be replaced
+            // as <math display="block"> is not valid inside it.
+            let node_to_replace = if display && is_only_child(elem.as_node()) {
+                replaceable_node(elem.as_node())
+            } else {
+                elem.as_node().clone()
+            };
+
+            match math::to_mathml(&tex, display) {
+                Ok(mathml) => {
+                    let fragment = parse_markup(&mathml);
+                    for node in fragment_nodes(&fragment) {
+                        node_to_replace.insert_before(node);
+                    }
+                }
+                Err(message) => {
+                    let code = NodeRef::new_element(
+                        el_name("code"),
+                        [
+                            attr("class", String::from("math-error")),
+                            attr("title", message.clone()),
+                        ],
+                    );
+                    code.append(NodeRef::new_text(format!("{} ({})", tex, message)));
+                    node_to_replace.insert_before(code);
+                }
+            }
+            node_to_replace.detach();
+        }
+    }
+}
+
+// Is this node the only non-whitespace child of its parent
+fn is_only_child(node: &NodeRef) -> bool {
+    node.parent().map_or(true, |parent| {
+        parent.children().all(|child| {
+            child == *node
+                || child
+                    .as_text()
+                    .map_or(false, |t| t.borrow().trim().is_empty())
+        })
+    })
+}
+
+// comrak wraps custom elements in a <p> tag so we need to replace that instead to avoid
+// generating invalid markup
+fn replaceable_node(elem: &NodeRef) -> NodeRef {
+    let p_qual = QualName::new(None, ns!(html), local_name!("p"));
+    match elem.parent() {
+        Some(parent) if parent.as_element().map_or(false, |e| e.name == p_qual) => parent,
+        Some(_) | None => elem.clone(),
+    }
+}
+
+// The nodes that make up a document returned by `parse_markup`, excluding the wrapping `<html>`
+// element
+fn fragment_nodes(doc: &NodeRef) -> Vec<NodeRef> {
+    doc.first_child()
+        .map(|html| html.children().collect())
+        .unwrap_or_default()
+}
+
 fn attr(name: &str, value: String) -> (ExpandedName, Attribute) {
     (
         ExpandedName::new(Namespace::from(""), LocalName::from(name)),
@@ -152,36 +759,17 @@ fn parse_markup(html: &str) -> NodeRef {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::settings as test_settings;
     use regex::Regex;
     use rocket::form::validate::Contains;
-    use std::path::PathBuf;
 
     const HTML: &str = "<h1>Test</h1><recently-changed-list></recently-changed-list>";
 
-    fn test_settings() -> Settings {
-        Settings {
-            pages_path: pages_path(),
-            author: "Test".to_string(),
-            author_url: "https://example.com/".to_string(),
-            copyright_start_year: 2020,
-            name: "Test Site".to_string(),
-            domain: "example.com".to_string(),
-            tagline: "For testing".to_string(),
-            sentry_dsn: None,
-        }
-    }
-
-    fn pages_path() -> PathBuf {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.extend(&["tests", "fixtures", "pages"]);
-        path
-    }
-
     #[test]
     fn recently_changed_list() {
         // Test that it replaces the custom element with a list of pages
         let doc = parse_markup(HTML);
-        RecentlyChangedList::process(&doc, &pages_path());
+        RecentlyChangedList::process(&doc, &test_settings());
 
         let processed = doc.to_string();
         let regex = Regex::new(r#"<ul>(<li><a href="/[^"]+">[^<]+</a><span class="smaller-font lighten"> updated <abbr title="\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z">[^<]+</abbr></span></li>)+</ul>"#).unwrap();
@@ -194,7 +782,7 @@ mod tests {
         // this is stripped.
         let html = "<h2>Recently Updated Pages</h2>\n<p><recently-changed-list></recently-changed-list></p>\n";
         let doc = parse_markup(html);
-        RecentlyChangedList::process(&doc, &pages_path());
+        RecentlyChangedList::process(&doc, &test_settings());
 
         let processed = doc.to_string();
         assert!(!processed.contains("<p>"));
@@ -203,7 +791,200 @@ mod tests {
     #[test]
     fn enhancing_markup_does_not_add_html_tag() {
         let markup = "<p>no HTML tag please</p>";
-        let enhanced = enhance_markup(markup, &test_settings());
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let enhanced = enhance_markup(markup, "test", &test_settings(), &adapter);
         assert_eq!(markup, enhanced);
     }
+
+    #[test]
+    fn callout() {
+        let html = "<blockquote>\n<p>[!WARNING]\nDo not do this.</p>\n</blockquote>\n";
+        let doc = parse_markup(html);
+        callouts(&doc);
+
+        let processed = doc.to_string();
+        assert_eq!(
+            processed,
+            "<html><aside class=\"callout warning\"><p class=\"callout-title\"><span aria-hidden=\"true\" class=\"callout-icon\">⚠</span> Warning</p>\n<p>Do not do this.</p>\n</aside>\n</html>"
+        );
+    }
+
+    #[test]
+    fn callout_custom_title() {
+        let html =
+            "<blockquote>\n<p>[!TIP] Faster builds</p>\n<p>Use a cache.</p>\n</blockquote>\n";
+        let doc = parse_markup(html);
+        callouts(&doc);
+
+        let processed = doc.to_string();
+        assert!(processed.contains(r#"<aside class="callout tip">"#));
+        assert!(processed.contains("</span> Faster builds</p>"));
+        assert!(processed.contains("<p>Use a cache.</p>"));
+        assert!(!processed.contains("[!TIP]"));
+    }
+
+    #[test]
+    fn plain_blockquote_is_untouched() {
+        let html = "<blockquote>\n<p>Just a quote</p>\n</blockquote>";
+        let doc = parse_markup(html);
+        callouts(&doc);
+
+        assert!(doc.to_string().contains("<blockquote>"));
+    }
+
+    #[test]
+    fn math() {
+        let html = "<p>Inline <pkb-math data-tex=\"x^2\"></pkb-math></p>\n<p><pkb-math display=\"block\" data-tex=\"\\frac{1}{2}\"></pkb-math></p>";
+        let doc = parse_markup(html);
+        Math::process(&doc);
+
+        let processed = doc.to_string();
+        assert!(processed.contains("<p>Inline <math"));
+        assert!(processed.contains(r#"display="block""#));
+        assert!(!processed.contains("<p><math"));
+        assert!(!processed.contains(MATH_ELEMENT));
+    }
+
+    #[test]
+    fn math_error() {
+        let html = "<p><pkb-math data-tex=\"\\frac{1}\"></pkb-math></p>";
+        let doc = parse_markup(html);
+        Math::process(&doc);
+
+        assert!(doc.to_string().contains(r#"<code class="math-error""#));
+    }
+
+    #[test]
+    fn diagram() {
+        let html =
+            "<pre><code class=\"language-svgbob\">+---+\n| A |--&gt; B\n+---+\n</code></pre>";
+        let doc = parse_markup(html);
+        render_diagrams(&doc);
+
+        let processed = doc.to_string();
+        assert!(processed.starts_with(r#"<html><figure class="diagram"><svg"#));
+        assert!(!processed.contains("<pre>"));
+    }
+
+    #[test]
+    fn csv_block() {
+        let html = "<pre><code class=\"language-csv\" data-meta=\"header\">name,count\nApples,3\nPears,12\n</code></pre>";
+        let doc = parse_markup(html);
+        render_csv_blocks(&doc);
+
+        let processed = doc.to_string();
+        assert!(processed
+            .contains(r#"<thead><tr><th>name</th><th class="numeric">count</th></tr></thead>"#));
+        assert!(processed.contains(r#"<tr><td>Pears</td><td class="numeric">12</td></tr>"#));
+        assert!(
+            processed.contains("<details class=\"csv-source\"><summary>View source</summary><pre>")
+        );
+    }
+
+    #[test]
+    fn csv_table_element() {
+        let html = "<p><csv-table src=\"data/sample.tsv\"></csv-table></p>";
+        let doc = parse_markup(html);
+        CsvTable::process(&doc, &test_settings());
+
+        let processed = doc.to_string();
+        assert!(processed.contains("<td>Red</td>"));
+        assert!(!processed.contains("<thead>"));
+        assert!(!processed.contains("<p>"));
+    }
+
+    #[test]
+    fn csv_table_element_rejects_parent_paths() {
+        let html = "<csv-table src=\"../fixtures/pages/data/sample.tsv\"></csv-table>";
+        let doc = parse_markup(html);
+        CsvTable::process(&doc, &test_settings());
+
+        assert!(doc.to_string().contains("invalid path"));
+    }
+
+    #[test]
+    fn csv_table_element_rejects_pages_and_private_files() {
+        let html = "<csv-table src=\"private.md\"></csv-table><csv-table src=\"private/data.csv\"></csv-table>";
+        let doc = parse_markup(html);
+        CsvTable::process(&doc, &test_settings());
+
+        let processed = doc.to_string();
+        assert!(processed.contains("not a CSV or TSV file"));
+        assert!(processed.contains("file is private"));
+        assert!(!processed.contains("Private page"));
+        assert!(!processed.contains("<table"));
+    }
+
+    #[test]
+    fn responsive_image() {
+        let html = r#"<p><img src="/files/images/wide.png" alt="Wide"></p>"#;
+        let doc = parse_markup(html);
+        responsive_images(&doc, &test_settings());
+
+        let processed = doc.to_string();
+        assert!(processed.contains(r#"width="1600""#));
+        assert!(processed.contains(r#"height="8""#));
+        assert!(processed.contains(r#"loading="lazy""#));
+        assert!(processed.contains(r#"srcset="/resized/480/images/wide.png 480w, /resized/960/images/wide.png 960w, /resized/1440/images/wide.png 1440w, /files/images/wide.png 1600w""#));
+    }
+
+    #[test]
+    fn remote_images_are_untouched() {
+        let html = r#"<img src="https://example.com/image.png">"#;
+        let doc = parse_markup(html);
+        responsive_images(&doc, &test_settings());
+
+        assert!(!doc.to_string().contains("loading"));
+    }
+
+    #[test]
+    fn summary_of_first_paragraph() {
+        let html = "<h1>Title</h1>\n<p>First  <em>paragraph</em>\ntext.</p><p>Second</p>";
+        assert_eq!(summary(html).as_deref(), Some("First paragraph text."));
+        assert_eq!(summary("<h1>No paragraphs</h1>"), None);
+
+        let long = format!("<p>{}</p>", "word ".repeat(100));
+        let summary = summary(&long).unwrap();
+        assert!(summary.chars().count() <= SUMMARY_LENGTH + 1);
+        assert!(summary.ends_with("word…"));
+    }
+
+    #[test]
+    fn include_page() {
+        let html = "<h2>Contact</h2>\n<p><include-page name=\"snippet\"></include-page></p>\n";
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let enhanced = enhance_markup(html, "test", &test_settings(), &adapter);
+
+        assert!(enhanced.contains("<p>Shared <strong>snippet</strong> content.</p>"));
+        assert!(!enhanced.contains("include-page"));
+    }
+
+    #[test]
+    fn include_page_with_csv_block() {
+        let html = "<p><include-page name=\"csv-snippet\"></include-page></p>";
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let enhanced = enhance_markup(html, "test", &test_settings(), &adapter);
+
+        assert_eq!(enhanced.matches("<table").count(), 1);
+        assert_eq!(enhanced.matches("<details").count(), 1);
+        assert!(enhanced.contains("<td>Apples</td>"));
+    }
+
+    #[test]
+    fn include_page_cycle() {
+        let html = "<p><include-page name=\"include-cycle\"></include-page></p>";
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let enhanced = enhance_markup(html, "test", &test_settings(), &adapter);
+
+        assert!(enhanced.contains("include cycle detected"));
+    }
+
+    #[test]
+    fn include_private_page() {
+        let html = "<p><include-page name=\"private\"></include-page></p>";
+        let adapter = SyntectAdapter::new(Some("base16-ocean.dark"));
+        let enhanced = enhance_markup(html, "test", &test_settings(), &adapter);
+
+        assert!(enhanced.contains("page is private"));
+    }
 }
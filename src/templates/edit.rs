@@ -0,0 +1,92 @@
+use crate::page::{Loaded, Page};
+use crate::web;
+
+markup::define! {
+    // Submits the form to the preview frame as the page is edited
+    Head() {
+        script[src="/public/js/editor.js", defer=true] {}
+    }
+
+    Edit<'a>(page: &'a Page<Loaded>, source: &'a str, mtime: u64, conflict: bool, error: Option<&'a str>) {
+        h2 { "Editing " a[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() } }
+
+        @if let Some(error) = error {
+            p."include-error" { @error }
+        }
+
+        @if *conflict {
+            p."include-error" {
+                "The page was changed while you were editing it. Your version is below, "
+                "saving it will replace the changes."
+            }
+            details.conflict {
+                summary { "Current version" }
+                pre { code { @page.source() } }
+            }
+        }
+
+        form.editor[method="post", action=uri!(web::edit::save(name=&page.slug())).to_string()] {
+            input[type="hidden", name="mtime", value=mtime];
+            textarea.monospace[name="source", rows="30", spellcheck="true"] { @source }
+            p {
+                label[for="summary"] { "Summary of changes" } br;
+                input[type="text", id="summary", name="summary", maxlength="200"];
+            }
+            p {
+                input[type="submit", value="Save"];
+                " "
+                button[type="submit", formaction=uri!(web::edit::preview).to_string(), formtarget="preview"] { "Preview" }
+            }
+        }
+
+        iframe.preview[name="preview", title="Preview"] {}
+    }
+
+    NewPage<'a>(title: &'a str, tags: &'a str, body: &'a str, error: Option<&'a str>) {
+        h2 { "New page" }
+
+        @if let Some(error) = error {
+            p."include-error" { @error }
+        }
+
+        form.editor[method="post", action=uri!(web::edit::create).to_string()] {
+            p {
+                label[for="title"] { "Title" } br;
+                input[type="text", id="title", name="title", value=title, required=true];
+            }
+            p {
+                label[for="tags"] { "Tags, separated by commas" } br;
+                input[type="text", id="tags", name="tags", value=tags];
+            }
+            textarea.monospace[name="body", rows="20"] { @body }
+            p {
+                label[for="summary"] { "Summary" } br;
+                input[type="text", id="summary", name="summary", maxlength="200"];
+            }
+            p { input[type="submit", value="Create"]; }
+        }
+    }
+
+    // A standalone document as the preview is shown in a frame
+    Preview(content: Result<String, String>) {
+        @markup::doctype()
+
+        html[lang="en"] {
+            head {
+                meta[charset="utf-8"];
+                title { "Preview" }
+                link[rel="stylesheet", href="/public/css/manrope.css", type="text/css"];
+                link[rel="stylesheet", href="/public/css/style.css", type="text/css"];
+            }
+
+            body {
+                article {
+                    @match content {
+                        Ok(content) => { @markup::raw(content) }
+                        Err(message) => { p."include-error" { "Unable to preview the page: " @message } }
+                    }
+                }
+            }
+        }
+    }
+}
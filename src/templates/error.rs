@@ -0,0 +1,21 @@
+use crate::related::Related;
+use crate::string_ext::StringExt;
+use crate::web;
+
+markup::define! {
+    NotFound<'a>(suggestions: &'a [Related]) {
+        h2 { "Page Not Found" }
+        p { "There is no page at this URL. The link may be broken or the page removed." }
+
+        @if !suggestions.is_empty() {
+            p { "Did you mean:" }
+            ul {
+                @for page in *suggestions {
+                    li { a[href=uri!(web::page::show(name=&page.name.to_slug())).to_string()] { @page.title } }
+                }
+            }
+        }
+
+        p { a[href=uri!(web::home).to_string()] { "Return to the home page" } }
+    }
+}
@@ -0,0 +1,228 @@
+//! Support for TeX math in Markdown.
+//!
+//! Math is delimited by `$...$` (inline) or `$$...$$` (display). Before the Markdown is rendered
+//! the math is replaced with `<pkb-math>` elements so that comrak does not interpret any of the
+//! TeX as Markdown. The elements are then converted to MathML by `decorators`.
+
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+
+pub(super) const MATH_ELEMENT: &str = "pkb-math";
+
+/// Replace the math in the supplied Markdown with `<pkb-math>` elements.
+///
+/// Math in fenced and indented code blocks and inline code spans is left untouched.
+pub(super) fn escape_math(markdown: &str) -> String {
+    let mut escaped = String::with_capacity(markdown.len());
+    let mut text = String::new();
+    let mut fence: Option<&str> = None;
+    // Indented code blocks start after a blank line, as they can't interrupt a paragraph, and
+    // continue over blank lines
+    let mut after_blank = true;
+    let mut indented_code = false;
+
+    for line in markdown.split_inclusive('\n') {
+        let marker = fence_marker(line);
+        let is_blank = line.trim().is_empty();
+        match (fence, marker) {
+            (None, _) if !is_blank && is_indented(line) && (after_blank || indented_code) => {
+                escaped.push_str(&escape_text(&text));
+                text.clear();
+                escaped.push_str(line);
+                indented_code = true;
+            }
+            (None, Some(marker)) => {
+                escaped.push_str(&escape_text(&text));
+                text.clear();
+                escaped.push_str(line);
+                fence = Some(marker);
+                indented_code = false;
+            }
+            (Some(open), Some(close)) if close.starts_with(open) && line.trim() == close => {
+                escaped.push_str(line);
+                fence = None;
+            }
+            (Some(_), _) => escaped.push_str(line),
+            (None, None) => {
+                indented_code &= is_blank;
+                text.push_str(line);
+            }
+        }
+        after_blank = is_blank;
+    }
+    escaped.push_str(&escape_text(&text));
+
+    escaped
+}
+
+/// Convert TeX to MathML
+pub(super) fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let style = if display {
+        DisplayStyle::Block
+    } else {
+        DisplayStyle::Inline
+    };
+    latex_to_mathml(tex, style).map_err(|err| err.to_string())
+}
+
+// Four spaces or a tab start an indented code block
+fn is_indented(line: &str) -> bool {
+    line.starts_with("    ") || line.starts_with('\t')
+}
+
+// Returns the run of backticks or tildes that opens a fenced code block, if present
+fn fence_marker(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let fence_char = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = trimmed
+        .find(|c: char| c != fence_char)
+        .unwrap_or(trimmed.len());
+    (len >= 3).then(|| &trimmed[..len])
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(i) = rest.find(|c: char| c == '$' || c == '`' || c == '\\') {
+        escaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with('\\') {
+            // Preserve escapes, such as \$
+            let len = rest.chars().nth(1).map_or(1, |c| 1 + c.len_utf8());
+            escaped.push_str(&rest[..len]);
+            rest = &rest[len..];
+        } else if rest.starts_with('`') {
+            // Copy code spans verbatim
+            let run = rest.find(|c: char| c != '`').unwrap_or(rest.len());
+            let len = rest[run..]
+                .find(&rest[..run])
+                .map_or(run, |close| run + close + run);
+            escaped.push_str(&rest[..len]);
+            rest = &rest[len..];
+        } else if let Some((tex, len)) = display_math(rest) {
+            escaped.push_str(&math_element(tex, true));
+            rest = &rest[len..];
+        } else if let Some((tex, len)) = inline_math(rest) {
+            escaped.push_str(&math_element(tex, false));
+            rest = &rest[len..];
+        } else {
+            escaped.push('$');
+            rest = &rest[1..];
+        }
+    }
+    escaped.push_str(rest);
+
+    escaped
+}
+
+// Parse $$...$$ from the start of text, returning the TeX and the length consumed
+fn display_math(text: &str) -> Option<(&str, usize)> {
+    let inner = text.strip_prefix("$$")?;
+    let end = inner.find("$$")?;
+    let tex = &inner[..end];
+    (!tex.trim().is_empty() && !tex.contains("\n\n")).then(|| (tex, end + 4))
+}
+
+// Parse $...$ from the start of text, returning the TeX and the length consumed.
+//
+// To avoid treating prices and the like as math the opening $ must not be followed by
+// whitespace, and the closing $ must not be preceded by whitespace or followed by a digit.
+fn inline_math(text: &str) -> Option<(&str, usize)> {
+    let inner = text.strip_prefix('$')?;
+    if inner.starts_with(|c: char| c.is_whitespace() || c == '$') {
+        return None;
+    }
+
+    let mut prev = None;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '\n' if prev == Some('\n') => return None,
+            '$' if prev != Some('\\') && !prev.map_or(true, char::is_whitespace) => {
+                let after = &inner[i + 1..];
+                if after.starts_with(|c: char| c.is_ascii_digit()) {
+                    return None;
+                }
+                return Some((&inner[..i], i + 2));
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+fn math_element(tex: &str, display: bool) -> String {
+    let mut tex_attr = String::with_capacity(tex.len());
+    for c in tex.trim().chars() {
+        match c {
+            '&' => tex_attr.push_str("&amp;"),
+            '"' => tex_attr.push_str("&quot;"),
+            '<' => tex_attr.push_str("&lt;"),
+            '>' => tex_attr.push_str("&gt;"),
+            // Newlines would split the element across lines, which Markdown may interpret
+            '\n' => tex_attr.push(' '),
+            c => tex_attr.push(c),
+        }
+    }
+
+    let display_attr = if display { r#" display="block""# } else { "" };
+    format!(
+        r#"<{el}{display_attr} data-tex="{tex_attr}"></{el}>"#,
+        el = MATH_ELEMENT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_math() {
+        assert_eq!(
+            escape_math("Euler: $e^{i\\pi} + 1 = 0$.\n"),
+            "Euler: <pkb-math data-tex=\"e^{i\\pi} + 1 = 0\"></pkb-math>.\n"
+        );
+    }
+
+    #[test]
+    fn test_display_math() {
+        assert_eq!(
+            escape_math("$$\nx < y\n$$\n"),
+            "<pkb-math display=\"block\" data-tex=\"x &lt; y\"></pkb-math>\n"
+        );
+    }
+
+    #[test]
+    fn test_not_math() {
+        let md = "It costs $5 or $10.\n";
+        assert_eq!(escape_math(md), md);
+        let md = "\\$x$ is escaped.\n";
+        assert_eq!(escape_math(md), md);
+        let md = "A `$x$` code span.\n";
+        assert_eq!(escape_math(md), md);
+        let md = "```\n$x$\n```\nand $y$\n";
+        assert_eq!(
+            escape_math(md),
+            "```\n$x$\n```\nand <pkb-math data-tex=\"y\"></pkb-math>\n"
+        );
+    }
+
+    #[test]
+    fn test_indented_code() {
+        let md = "Code:\n\n    let price = $x$;\n\n    $y$\n\nand $z$\n";
+        assert_eq!(
+            escape_math(md),
+            "Code:\n\n    let price = $x$;\n\n    $y$\n\nand <pkb-math data-tex=\"z\"></pkb-math>\n"
+        );
+        // A paragraph continuation line is not code
+        let md = "Some\n    $x$\n";
+        assert_eq!(
+            escape_math(md),
+            "Some\n    <pkb-math data-tex=\"x\"></pkb-math>\n"
+        );
+    }
+}
@@ -1,16 +1,18 @@
 use comrak::plugins::syntect::SyntectAdapter;
 
-use crate::page::{Loaded, Page};
+use crate::page::{Draft, Loaded, Page};
+use crate::related::Related;
 use crate::settings::Settings;
-use crate::templates::decorators::enhance_markup;
+use crate::string_ext::StringExt;
+use crate::templates::decorators::{enhance_markup, summary};
 use crate::{templates, web};
 
 markup::define! {
-    Show<'a>(page: &'a Page<Loaded>, settings: &'a Settings, adapter: &'a SyntectAdapter) {
+    Show<'a>(page: &'a Page<Loaded>, content: &'a str, related: &'a [Related], editable: bool) {
         article {
-            h1 { a."no-decoration"[href=uri!(web::page::show(name=&page.name)).to_string()] { @page.title() } }
+            h1 { a."no-decoration"[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() } }
 
-            @markup::raw(enhance_markup(&templates::markdown(page.markdown(), adapter), settings))
+            @markup::raw(content)
 
             div."smaller-font lighten top-gap-double-em shaded-panel" {
                 "Last modified: " abbr[title=page.mtime_rfc3339()] { @page.mtime_date() }
@@ -19,17 +21,125 @@ markup::define! {
                         li { a[href=uri!(web::tag::show(name=tag)).to_string(), rel="tag"] { "#" @tag } }
                     }
                 }
+                @if *editable {
+                    a[href=uri!(web::edit::edit(name=&page.slug())).to_string()] { "Edit this page" }
+                }
+            }
+
+            @if !related.is_empty() {
+                section.related {
+                    h2 { "Related pages" }
+                    ul {
+                        @for related_page in *related {
+                            li { a[href=uri!(web::page::show(name=&related_page.name.to_slug())).to_string()] { @related_page.title } }
+                        }
+                    }
+                }
             }
         }
     }
 
-    Index<'a>(pages: &'a [Page<Loaded>]) {
+    // Metadata for link previews and search engines
+    Head<'a>(page: &'a Page<Loaded>, settings: &'a Settings, description: Option<&'a str>) {
+        @let url = canonical_url(page, settings);
+        link[rel="canonical", href=&url];
+        @if let Some(description) = description {
+            meta[name="description", content=description];
+            meta[property="og:description", content=description];
+            meta[name="twitter:description", content=description];
+        }
+        meta[property="og:type", content="article"];
+        meta[property="og:title", content=page.title()];
+        meta[property="og:url", content=&url];
+        meta[property="og:site_name", content=&settings.name];
+        meta[property="article:modified_time", content=page.mtime_rfc3339()];
+        @for tag in page.tags() {
+            meta[property="article:tag", content=tag];
+        }
+        meta[name="twitter:card", content="summary"];
+        meta[name="twitter:title", content=page.title()];
+        script[type="application/ld+json"] {
+            @markup::raw(json_ld(page, settings, &url, *description))
+        }
+    }
+
+    Index<'a>(pages: &'a [Page<Loaded>], can_create: bool) {
         h2 { "Index" }
 
+        @if *can_create {
+            p { a[href=uri!(web::edit::new_page).to_string()] { "New page" } }
+        }
+
         ul {
             @for page in *pages {
-                li { a[href=uri!(web::page::show(name=&page.name)).to_string()] { @page.title() } }
+                li { a[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() } }
             }
         }
     }
 }
+
+/// Render the content of a page to HTML
+pub(crate) fn render(page: &Page<Loaded>, settings: &Settings, adapter: &SyntectAdapter) -> String {
+    enhance_markup(
+        &templates::page_html(page, settings, adapter),
+        &page.name,
+        settings,
+        adapter,
+    )
+}
+
+/// Render a draft of a Markdown page to HTML
+pub(crate) fn render_draft(draft: &Draft, settings: &Settings, adapter: &SyntectAdapter) -> String {
+    enhance_markup(
+        &templates::draft_html(draft, settings, adapter),
+        "preview",
+        settings,
+        adapter,
+    )
+}
+
+/// The description of a page from its front-matter, or failing that the first paragraph of its
+/// rendered content
+pub(crate) fn description(page: &Page<Loaded>, content: &str) -> Option<String> {
+    page.description()
+        .map(ToString::to_string)
+        .or_else(|| summary(content))
+}
+
+pub(crate) fn canonical_url(page: &Page<Loaded>, settings: &Settings) -> String {
+    let path = if page.name == "home" {
+        uri!(web::home)
+    } else {
+        uri!(web::page::show(name = &page.slug()))
+    };
+    format!("https://{}{}", settings.domain, path)
+}
+
+// schema.org Article metadata
+fn json_ld(
+    page: &Page<Loaded>,
+    settings: &Settings,
+    url: &str,
+    description: Option<&str>,
+) -> String {
+    let article = serde_json::json!({
+        "@context": "https://schema.org",
+        "@type": "Article",
+        "headline": page.title(),
+        "description": description,
+        "url": url,
+        "dateModified": page.mtime_rfc3339(),
+        "keywords": page.tags(),
+        "author": {
+            "@type": "Person",
+            "name": settings.author,
+            "url": settings.author_url,
+        },
+        "publisher": {
+            "@type": "Organization",
+            "name": settings.name,
+        },
+    });
+    // Escape < so that the JSON can't close the script element
+    article.to_string().replace('<', "\\u003c")
+}
@@ -0,0 +1,94 @@
+use crate::page::{Loaded, Page};
+use crate::render_cache::RenderCacheStats;
+use crate::report::Staleness;
+use crate::web;
+
+markup::define! {
+    // Reports shouldn't show up in search results
+    Head() {
+        meta[name="robots", content="noindex"];
+    }
+
+    Index(render_cache: RenderCacheStats) {
+        h2 { "Reports" }
+
+        ul {
+            li { a[href=uri!(web::report::orphans).to_string()] { "Orphaned pages" } }
+            li { a[href=uri!(web::report::untagged).to_string()] { "Untagged pages" } }
+            li { a[href=uri!(web::report::stale).to_string()] { "Stale pages" } }
+            li { a[href=uri!(web::report::collisions).to_string()] { "Slug collisions" } }
+        }
+
+        h3 { "Render cache" }
+        p.lighten {
+            @render_cache.hits " hits, " @render_cache.misses " misses. "
+            @render_cache.entries " pages using " @render_cache.size_kib() " of "
+            @render_cache.limit_kib() " KiB."
+        }
+    }
+
+    Pages<'a>(heading: &'a str, description: &'a str, pages: &'a [Page<Loaded>]) {
+        h2 { @heading }
+        p.lighten { @description }
+
+        @if pages.is_empty() {
+            p { "Nothing to report." }
+        } else {
+            ul {
+                @for page in *pages {
+                    li { a[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() } }
+                }
+            }
+        }
+    }
+
+    Stale<'a>(pages: &'a [(Page<Loaded>, Staleness)], stale_after_days: u64) {
+        h2 { "Stale pages" }
+        p.lighten {
+            "Pages that have not been modified in the last " @stale_after_days " days, or are past their review by date."
+        }
+
+        @if pages.is_empty() {
+            p { "Nothing to report." }
+        } else {
+            ul {
+                @for (page, staleness) in *pages {
+                    li {
+                        a[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() }
+                        span."smaller-font lighten" {
+                            @match staleness {
+                                Staleness::NotModified => {
+                                    " last modified "
+                                    abbr[title=page.mtime_rfc3339()] { @page.mtime_date() }
+                                }
+                                Staleness::ReviewDue(date) => {
+                                    " review due " @date.to_string()
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Collisions<'a>(collisions: &'a [(String, Vec<String>)]) {
+        h2 { "Slug collisions" }
+        p.lighten {
+            "Pages with file names that have the same slug. Only one of each can be reached, rename the others."
+        }
+
+        @if collisions.is_empty() {
+            p { "Nothing to report." }
+        } else {
+            ul {
+                @for (slug, names) in *collisions {
+                    li {
+                        a[href=uri!(web::page::show(name=slug)).to_string()] { "/" @slug }
+                        span."smaller-font lighten" { " " @names.join(", ") }
+                    }
+                }
+            }
+        }
+    }
+}
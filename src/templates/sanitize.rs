@@ -0,0 +1,149 @@
+use ammonia::Builder;
+
+/// Custom elements and the attributes they use, see the decorators module
+const CUSTOM_ELEMENTS: &[(&str, &[&str])] = &[
+    ("include-page", &["name"]),
+    ("recently-changed-list", &[]),
+    ("csv-table", &["src", "header"]),
+    ("pkb-math", &["display", "data-tex"]),
+];
+
+/// MathML elements generated by latex2mathml
+const MATH_ELEMENTS: &[&str] = &[
+    "math",
+    "semantics",
+    "annotation",
+    "merror",
+    "mfrac",
+    "mi",
+    "mmultiscripts",
+    "mn",
+    "mo",
+    "mover",
+    "mpadded",
+    "mphantom",
+    "mprescripts",
+    "mroot",
+    "mrow",
+    "ms",
+    "mspace",
+    "msqrt",
+    "mstyle",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "none",
+];
+
+/// Presentation attributes of the MathML elements
+const MATH_ATTRIBUTES: &[&str] = &[
+    "accent",
+    "accentunder",
+    "columnalign",
+    "columnlines",
+    "columnspacing",
+    "depth",
+    "display",
+    "displaystyle",
+    "fence",
+    "form",
+    "height",
+    "largeop",
+    "linethickness",
+    "lspace",
+    "mathvariant",
+    "maxsize",
+    "minsize",
+    "movablelimits",
+    "rowalign",
+    "rowlines",
+    "rowspacing",
+    "rspace",
+    "scriptlevel",
+    "separator",
+    "stretchy",
+    "symmetric",
+    "voffset",
+    "width",
+];
+
+/// Remove anything that could run script from rendered pages, such as `<script>` elements,
+/// event handler attributes, and `javascript:` URLs.
+///
+/// The allowlist is ammonia's defaults plus the custom elements, the markup generated by comrak
+/// and syntax highlighting, and the markup added by the decorators.
+pub(crate) fn sanitize(html: &str) -> String {
+    let mut builder = Builder::default();
+    builder
+        .add_tags(CUSTOM_ELEMENTS.iter().map(|(tag, _)| *tag))
+        .add_tags(["input", "section"])
+        .add_tags(MATH_ELEMENTS.iter().copied())
+        // Callouts, diagrams, and CSV tables
+        .add_tags(["aside", "figure", "details", "summary"])
+        .add_generic_attributes(["id", "class"])
+        .add_tag_attributes("span", ["aria-hidden"])
+        // Responsive images
+        .add_tag_attributes("img", ["srcset", "sizes", "width", "height", "loading"])
+        // Syntax highlighting uses inline styles
+        .add_tag_attributes("pre", ["style"])
+        .add_tag_attributes("span", ["style"])
+        // Fence options, like `csv header`
+        .add_tag_attributes("code", ["data-meta"])
+        // Task lists and footnotes
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("a", ["data-footnote-ref", "data-footnote-backref"])
+        .add_tag_attributes("section", ["data-footnotes"])
+        // Don't add rel="noopener noreferrer" to every link
+        .link_rel(None);
+    for (tag, attributes) in CUSTOM_ELEMENTS {
+        builder.add_tag_attributes(*tag, attributes.iter().copied());
+    }
+    for tag in MATH_ELEMENTS {
+        builder.add_tag_attributes(*tag, MATH_ATTRIBUTES.iter().copied());
+    }
+    builder.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_script() {
+        assert_eq!(
+            sanitize(r#"<p onclick="alert(1)">Hi<script>alert(2)</script></p>"#),
+            "<p>Hi</p>"
+        );
+        assert_eq!(
+            sanitize(r#"<a href="javascript:alert(1)">link</a>"#),
+            "<a>link</a>"
+        );
+    }
+
+    #[test]
+    fn keeps_custom_elements() {
+        let html = r#"<include-page name="snippet"></include-page><pkb-math display="block" data-tex="x^2"></pkb-math>"#;
+        assert_eq!(sanitize(html), html);
+    }
+
+    #[test]
+    fn keeps_highlighting() {
+        let html = r#"<pre style="background-color:#2b303b;"><code class="language-rust" data-meta="header"><span style="color:#b48ead;">fn</span></code></pre>"#;
+        assert_eq!(sanitize(html), html);
+    }
+
+    #[test]
+    fn keeps_decorator_markup() {
+        let html = r#"<aside class="callout tip"><p class="callout-title"><span aria-hidden="true" class="callout-icon">💡</span> Tip</p></aside><img src="/files/a.png" srcset="/resized/480/a.png 480w" width="960" height="540" loading="lazy">"#;
+        assert_eq!(sanitize(html), html);
+
+        let html = r#"<math display="block"><mfrac><mn>1</mn><mi mathvariant="normal">x</mi></mfrac></math>"#;
+        assert_eq!(sanitize(html), html);
+    }
+}
@@ -8,7 +8,7 @@ markup::define! {
         ul {
             @for page in tag.sorted_pages() {
                 li {
-                    a[href=uri!(web::page::show(name=&page.name)).to_string()] { @page.title() }
+                    a[href=uri!(web::page::show(name=&page.slug())).to_string()] { @page.title() }
                 }
             }
         }
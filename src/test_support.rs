@@ -0,0 +1,83 @@
+//! Fixtures shared by the unit tests
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+use rocket::figment::Figment;
+
+use crate::settings::{
+    AuthSettings, CacheSettings, GitSettings, MarkdownOptions, SecuritySettings, Settings,
+};
+
+/// The directory of the fixture pages
+pub(crate) fn pages_path() -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.extend(&["tests", "fixtures", "pages"]);
+    path
+}
+
+/// Settings for the fixture pages with every option at its default
+pub(crate) fn settings() -> Settings {
+    Settings {
+        pages_path: pages_path(),
+        author: "Test".to_string(),
+        author_url: "https://example.com/".to_string(),
+        copyright_start_year: 2020,
+        name: "Test Site".to_string(),
+        domain: "example.com".to_string(),
+        tagline: "For testing".to_string(),
+        sentry_dsn: None,
+        attachments_path: None,
+        image_cache_path: None,
+        stale_after_days: None,
+        render_cache_bytes: None,
+        markdown: MarkdownOptions::default(),
+        cache: CacheSettings::default(),
+        security: SecuritySettings::default(),
+        auth: AuthSettings::default(),
+        git: GitSettings::default(),
+    }
+}
+
+/// Rocket configuration with the required settings, for launching the app with `pages_path`
+pub(crate) fn figment(pages_path: &Path) -> Figment {
+    Figment::from(rocket::Config::default())
+        .merge(("pages_path", pages_path))
+        .merge(("author", "Test"))
+        .merge(("author_url", "https://example.com/"))
+        .merge(("copyright_start_year", 2020))
+        .merge(("name", "Test Site"))
+        .merge(("domain", "example.com"))
+        .merge(("tagline", "For testing"))
+}
+
+// Distinguishes the directories of tests running concurrently
+static DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An empty directory in the system temp dir that is removed, along with its contents, when
+/// dropped. The directory is removed even when the test fails.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "pkb-{}-{}-{}",
+            name,
+            process::id(),
+            DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
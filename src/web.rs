@@ -1,70 +1,158 @@
+mod api;
+pub(crate) mod auth;
+mod compression;
+pub(crate) mod edit;
+pub(crate) mod file;
 pub(crate) mod page;
+pub(crate) mod report;
+mod security;
 mod sitemap;
 pub(crate) mod tag;
 
 use std::convert::Infallible;
+use std::io::Cursor;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 
 use comrak::plugins::syntect::SyntectAdapter;
 use rocket::fairing::{self, AdHoc, Fairing, Info, Kind};
-use rocket::fs::FileServer;
-use rocket::http::{Header, Status};
+use rocket::http::{Header, Method, Status};
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::content::RawHtml;
-use rocket::response::Responder;
+use rocket::response::{self, Responder};
 use rocket::{Build, Data, Request, Response, Rocket};
 use rocket::{Catcher, State};
 use sentry::types::Dsn;
+use sha2::{Digest, Sha256};
 use time::format_description::FormatItem;
 use time::macros::format_description;
 use time::{OffsetDateTime, PrimitiveDateTime};
 
-use crate::settings::Settings;
-use crate::PkbError;
+use crate::page::Page;
+use crate::related::RelatedPages;
+use crate::render_cache::RenderCache;
+use crate::settings::{CacheClass, Settings};
+use crate::templates::error::NotFound;
+use crate::templates::{Layout, Nil};
+use crate::web::auth::{Challenge, VerifiedCredentials, Viewer};
+use crate::web::compression::Compression;
+use crate::web::security::SecurityHeaders;
+use crate::{OffsetDateTimeExt, PkbError};
 
 #[derive(Responder)]
 pub(crate) enum CachedHtml {
     #[response(status = 304)]
     NotModified(CacheControl<LastModified<()>>),
     #[response(content_type = "html")]
-    Html(CacheControl<LastModified<String>>),
+    Html(CacheControl<LastModified<ETag<String>>>),
+}
+
+#[derive(Responder)]
+pub(crate) enum CachedFile {
+    #[response(status = 304)]
+    NotModified(CacheControl<LastModified<()>>),
+    File(CacheControl<LastModified<ETag<file::Attachment>>>),
+}
+
+/// Responses that can indicate that the resource has not been modified
+pub(crate) trait NotModified {
+    fn not_modified(policy: CachePolicy, last_modified: SystemTime) -> Self;
 }
 
 pub(crate) struct IfModifiedSince(OffsetDateTime);
 
+/// Only make a change if the resource hasn't been modified since this time
+pub(crate) struct IfUnmodifiedSince(OffsetDateTime);
+
 pub fn rocket() -> Rocket<Build> {
     let adapter = Arc::new(SyntectAdapter::new(Some("base16-ocean.dark")));
 
     rocket::build()
         .attach(RequestTimer(None))
+        // Compression changes the ETag so it has to run before IfNoneMatch
+        .attach(Compression)
+        .attach(IfNoneMatch)
+        .attach(SecurityHeaders)
         .manage(adapter)
+        .manage(RelatedPages::default())
+        .manage(VerifiedCredentials::default())
         .mount("/", routes![home, sitemap::robots, sitemap::show])
         .mount("/", page::routes())
         .mount("/", tag::routes())
+        .mount("/", file::routes())
+        .mount("/", report::routes())
+        .mount("/", edit::routes())
+        .mount("/", api::routes())
+        .mount("/", routes![file::public])
         .attach(AdHoc::config::<Settings>())
         .attach(init_settings())
-        .mount("/public", FileServer::from("public"))
+        .attach(init_render_cache())
+        .attach(check_slugs())
+        .attach(purge_caches())
         .register("/", catchers())
+        .register("/api", api::catchers())
 }
 
 pub fn catchers() -> Vec<Catcher> {
-    catchers![not_found, internal_server_error]
+    catchers![unauthorized, not_found, internal_server_error]
 }
 
 #[get("/")]
 pub(crate) fn home<'r>(
     settings: &State<Settings>,
     adapter: &State<Arc<SyntectAdapter>>,
+    related: &State<RelatedPages>,
+    render_cache: &State<RenderCache>,
+    viewer: Viewer,
     if_modified_since: Option<IfModifiedSince>,
 ) -> Result<CachedHtml, PkbError> {
-    page::show("home", settings, adapter, if_modified_since)
+    let home = Page::home(&settings.pages_path).ok_or(PkbError::PageNotFound)?;
+    page::show_page(
+        home,
+        settings,
+        adapter,
+        related,
+        render_cache,
+        viewer,
+        if_modified_since,
+    )
+}
+
+#[catch(401)]
+fn unauthorized(req: &Request<'_>) -> Challenge {
+    Challenge::new(req.rocket().state::<Settings>())
 }
 
+/// Maximum number of suggestions on the not found page
+const SUGGESTION_LIMIT: usize = 5;
+
 #[catch(404)]
-fn not_found() -> RawHtml<&'static str> {
+fn not_found(req: &Request<'_>) -> RawHtml<String> {
     const BODY: &str = include_str!("templates/404.html");
-    RawHtml(BODY)
+    let settings = match req.rocket().state::<Settings>() {
+        Some(settings) => settings,
+        None => return RawHtml(BODY.to_string()),
+    };
+
+    // Only paths that could be a page get suggestions, misses under /files, /api, etc. don't
+    let segments = req.uri().path().segments();
+    let suggestions = match (segments.len(), req.rocket().state::<RelatedPages>()) {
+        (1, Some(related)) => related.similar(
+            segments.get(0).unwrap_or_default(),
+            SUGGESTION_LIMIT,
+            settings,
+        ),
+        _ => Vec::new(),
+    };
+    let page = Layout {
+        settings,
+        title: "Not Found",
+        head: Nil {},
+        body: NotFound {
+            suggestions: &suggestions,
+        },
+    };
+    RawHtml(page.to_string())
 }
 
 #[catch(500)]
@@ -77,6 +165,40 @@ pub fn init_settings() -> AdHoc {
     AdHoc::try_on_ignite("Init settings", install_sentry)
 }
 
+pub fn init_render_cache() -> AdHoc {
+    AdHoc::on_ignite("Init render cache", |rocket| async {
+        let limit = rocket
+            .state::<Settings>()
+            .expect("no settings in state")
+            .render_cache_size();
+        rocket.manage(RenderCache::new(limit))
+    })
+}
+
+/// Warn at launch about pages that can't be reached because another page has the same slug
+pub fn check_slugs() -> AdHoc {
+    AdHoc::on_liftoff("Check slugs", |rocket| {
+        Box::pin(async move {
+            let settings = rocket.state::<Settings>().expect("no settings in state");
+            for (slug, names) in crate::report::collisions(&settings.pages_path, None) {
+                warn!("pages share the slug '{}': {}", slug, names.join(", "));
+            }
+        })
+    })
+}
+
+/// Purge changed pages from the configured caches
+pub fn purge_caches() -> AdHoc {
+    AdHoc::on_liftoff("Purge caches", |rocket| {
+        Box::pin(async move {
+            let settings = rocket.state::<Settings>().expect("no settings in state");
+            if !settings.cache.purge_endpoints.is_empty() {
+                crate::purge::watch(settings);
+            }
+        })
+    })
+}
+
 async fn install_sentry(rocket: Rocket<Build>) -> fairing::Result {
     let settings = rocket.state::<Settings>().expect("no settings in state");
 
@@ -137,6 +259,51 @@ impl Fairing for RequestTimer {
     }
 }
 
+/// Responds with 304 Not Modified when the `ETag` of a response matches the `If-None-Match`
+/// header of the request. The response has to be generated to know its `ETag` so this only saves
+/// sending the body.
+struct IfNoneMatch;
+
+#[rocket::async_trait]
+impl Fairing for IfNoneMatch {
+    fn info(&self) -> Info {
+        Info {
+            name: "If-None-Match",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !matches!(request.method(), Method::Get | Method::Head)
+            || response.status() != Status::Ok
+        {
+            return;
+        }
+
+        let is_match = match (
+            request.headers().get_one("if-none-match"),
+            response.headers().get_one("etag"),
+        ) {
+            (Some(if_none_match), Some(etag)) => etag_matches(if_none_match, etag),
+            _ => false,
+        };
+        if is_match {
+            response.set_status(Status::NotModified);
+            response.set_sized_body(0, Cursor::new(""));
+        }
+    }
+}
+
+// If-None-Match uses the weak comparison, where W/"1" matches "1"
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag)
+}
+
 /// Request guard used to retrieve the start time of a request.
 #[derive(Copy, Clone)]
 pub struct StartTime(pub Instant);
@@ -154,28 +321,121 @@ impl<'r> FromRequest<'r> for StartTime {
     }
 }
 
-const CACHE_TIME: Duration = Duration::from_secs(60);
+/// How long a response may be cached for
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct CachePolicy {
+    max_age: Duration,
+    stale_while_revalidate: Option<Duration>,
+    /// Only the client may cache the response, not shared caches
+    private: bool,
+    /// The response depends on who the user is
+    varies_by_user: bool,
+}
+
+impl CachePolicy {
+    pub(crate) fn new(settings: &Settings, class: CacheClass) -> Self {
+        CachePolicy {
+            max_age: settings.cache.max_age(class),
+            stale_while_revalidate: settings
+                .cache
+                .stale_while_revalidate
+                .map(Duration::from_secs),
+            private: false,
+            varies_by_user: false,
+        }
+    }
+
+    pub(crate) fn private(self) -> Self {
+        CachePolicy {
+            private: true,
+            ..self
+        }
+    }
+
+    pub(crate) fn varies_by_user(self) -> Self {
+        CachePolicy {
+            varies_by_user: true,
+            ..self
+        }
+    }
+
+    // `max_age_directive` is max-age or s-maxage
+    fn cache_control(&self, max_age_directive: &str) -> String {
+        let visibility = if self.private { "private" } else { "public" };
+        let mut value = format!(
+            "{}={}, {}",
+            max_age_directive,
+            self.max_age.as_secs(),
+            visibility
+        );
+        if let Some(stale) = self.stale_while_revalidate {
+            value.push_str(&format!(", stale-while-revalidate={}", stale.as_secs()));
+        }
+        value
+    }
+}
 
 impl CachedHtml {
-    fn html(last_modified: SystemTime, content: String) -> Self {
+    fn html(policy: CachePolicy, last_modified: SystemTime, content: String) -> Self {
+        let etag = content_etag(content.as_bytes());
         CachedHtml::Html(expires_in(
-            CACHE_TIME,
-            fresh_when(last_modified.max(crate::BUILD_DATE.into()), content),
+            policy,
+            fresh_when(
+                last_modified.max(crate::BUILD_DATE.into()),
+                tagged(etag, content),
+            ),
         ))
     }
+}
 
-    fn not_modified(last_modified: SystemTime) -> Self {
+impl NotModified for CachedHtml {
+    fn not_modified(policy: CachePolicy, last_modified: SystemTime) -> Self {
         CachedHtml::NotModified(expires_in(
-            CACHE_TIME,
+            policy,
             fresh_when(last_modified.max(crate::BUILD_DATE.into()), ()),
         ))
     }
 }
 
-#[derive(Responder)]
+impl CachedFile {
+    fn file(
+        policy: CachePolicy,
+        last_modified: SystemTime,
+        len: u64,
+        attachment: file::Attachment,
+    ) -> Self {
+        CachedFile::File(expires_in(
+            policy,
+            fresh_when(
+                last_modified,
+                tagged(file_etag(last_modified, len), attachment),
+            ),
+        ))
+    }
+}
+
+impl NotModified for CachedFile {
+    fn not_modified(policy: CachePolicy, last_modified: SystemTime) -> Self {
+        CachedFile::NotModified(expires_in(policy, fresh_when(last_modified, ())))
+    }
+}
+
 pub(crate) struct CacheControl<R> {
     inner: R,
     cache_control: Header<'static>,
+    varies_by_user: bool,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for CacheControl<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.inner.respond_to(req)?;
+        response.set_header(self.cache_control);
+        if self.varies_by_user {
+            // The headers users are authenticated by, see `auth::Viewer`
+            response.adjoin_raw_header("Vary", "Authorization, X-Forwarded-User");
+        }
+        Ok(response)
+    }
 }
 
 #[derive(Responder)]
@@ -184,29 +444,37 @@ pub(crate) struct LastModified<R> {
     last_modified: Header<'static>,
 }
 
+#[derive(Responder)]
+pub(crate) struct ETag<R> {
+    inner: R,
+    etag: Header<'static>,
+}
+
 fn cache_in_varnish<'r, 'o: 'r, R: Responder<'r, 'o>>(
-    duration: Duration,
+    policy: CachePolicy,
     responder: R,
 ) -> CacheControl<R> {
-    let value = format!("s-maxage={}, public", duration.as_secs());
+    let value = policy.cache_control("s-maxage");
     let cache_control = Header::new(rocket::http::hyper::header::CACHE_CONTROL.as_str(), value);
 
     CacheControl {
         inner: responder,
         cache_control,
+        varies_by_user: policy.varies_by_user,
     }
 }
 
 fn expires_in<'r, 'o: 'r, R: Responder<'r, 'o>>(
-    duration: Duration,
+    policy: CachePolicy,
     responder: R,
 ) -> CacheControl<R> {
-    let value = format!("max-age={}, public", duration.as_secs());
+    let value = policy.cache_control("max-age");
     let cache_control = Header::new(rocket::http::hyper::header::CACHE_CONTROL.as_str(), value);
 
     CacheControl {
         inner: responder,
         cache_control,
+        varies_by_user: policy.varies_by_user,
     }
 }
 
@@ -227,11 +495,43 @@ fn fresh_when<'r, 'o: 'r, R: Responder<'r, 'o>>(
     }
 }
 
+fn tagged<'r, 'o: 'r, R: Responder<'r, 'o>>(etag: String, responder: R) -> ETag<R> {
+    let etag = Header::new(rocket::http::hyper::header::ETAG.as_str(), etag);
+    ETag {
+        inner: responder,
+        etag,
+    }
+}
+
+/// A strong entity tag derived from the content of a response. The hash must be stable across
+/// builds so that tags remain valid after the server is upgraded.
+fn content_etag(content: &[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let hex = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("\"{}\"", hex)
+}
+
+/// An entity tag for a file derived from its modification time and length, which unlike
+/// Last-Modified changes when a file is modified more than once a second
+fn file_etag(last_modified: SystemTime, len: u64) -> String {
+    let nanos = last_modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    format!("\"{:x}-{:x}\"", nanos, len)
+}
+
 impl IfModifiedSince {
     /// Returns a not modified response if fresh, None otherwise
-    fn is_fresh(&self, last_modified: SystemTime) -> Option<CachedHtml> {
+    fn is_fresh<R: NotModified>(
+        &self,
+        policy: CachePolicy,
+        last_modified: SystemTime,
+    ) -> Option<R> {
         (OffsetDateTime::from(last_modified) <= self.0)
-            .then(|| CachedHtml::not_modified(last_modified))
+            .then(|| R::not_modified(policy, last_modified))
     }
 }
 
@@ -240,15 +540,105 @@ impl<'r> FromRequest<'r> for IfModifiedSince {
     type Error = Infallible;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        match req.headers().get_one("if-modified-since") {
-            Some(timestamp) if timestamp.ends_with(" GMT") => {
-                PrimitiveDateTime::parse(timestamp, HTTP_DATE)
-                    .map(|last_modified| {
-                        Outcome::Success(IfModifiedSince(last_modified.assume_utc()))
-                    })
-                    .unwrap_or_else(|_| Outcome::Forward(Status::BadRequest))
-            }
-            Some(_) | None => Outcome::Forward(Status::NotFound),
+        // If-Modified-Since is ignored when If-None-Match is present, the ETag is checked by the
+        // IfNoneMatch fairing instead
+        if req.headers().contains("if-none-match") {
+            return Outcome::Forward(Status::NotFound);
+        }
+
+        // Invalid dates are ignored, as if the header was absent
+        match req
+            .headers()
+            .get_one("if-modified-since")
+            .and_then(parse_http_date)
+        {
+            Some(since) => Outcome::Success(IfModifiedSince(since)),
+            None => Outcome::Forward(Status::NotFound),
         }
     }
 }
+
+impl IfUnmodifiedSince {
+    /// Whether a resource last modified at `last_modified` fails the precondition. HTTP dates
+    /// have whole seconds so the fraction of the modification time is ignored.
+    pub(crate) fn is_modified(&self, last_modified: SystemTime) -> bool {
+        OffsetDateTime::from(last_modified).truncate_seconds() > self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfUnmodifiedSince {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // Invalid dates are ignored, as if the header was absent
+        match req
+            .headers()
+            .get_one("if-unmodified-since")
+            .and_then(parse_http_date)
+        {
+            Some(since) => Outcome::Success(IfUnmodifiedSince(since)),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+fn parse_http_date(value: &str) -> Option<OffsetDateTime> {
+    if !value.ends_with(" GMT") {
+        return None;
+    }
+    PrimitiveDateTime::parse(value, HTTP_DATE)
+        .ok()
+        .map(PrimitiveDateTime::assume_utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    // Rocket refuses to launch when routes collide
+    #[rocket::async_test]
+    async fn routes_do_not_collide() {
+        let figment = test_support::figment(&test_support::pages_path());
+
+        let result = rocket().configure(figment).ignite().await;
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches(r#""abc""#, r#""abc""#));
+        assert!(etag_matches(r#"W/"abc""#, r#""abc""#));
+        assert!(etag_matches(r#""xyz", "abc""#, r#""abc""#));
+        assert!(etag_matches("*", r#""abc""#));
+        assert!(!etag_matches(r#""xyz""#, r#""abc""#));
+        assert!(!etag_matches("abc", r#""abc""#));
+    }
+
+    #[test]
+    fn test_content_etag() {
+        assert_eq!(content_etag(b"content"), content_etag(b"content"));
+        assert_ne!(content_etag(b"content"), content_etag(b"changed"));
+        // Tags don't change between builds
+        assert_eq!(
+            content_etag(b"content"),
+            r#""ed7002b439e9ac845f22357d822bac14""#
+        );
+    }
+
+    #[test]
+    fn test_if_unmodified_since() {
+        assert_eq!(parse_http_date("Sun, 30 Jun 2024 12:00:00"), None);
+        assert_eq!(parse_http_date("yesterday GMT"), None);
+        let since = IfUnmodifiedSince(parse_http_date("Sun, 30 Jun 2024 12:00:00 GMT").unwrap());
+
+        let at = |seconds, nanos| {
+            SystemTime::from(time::macros::datetime!(2024-06-30 12:00:00 UTC))
+                + Duration::new(seconds, nanos)
+        };
+        assert!(!since.is_modified(at(0, 0)));
+        assert!(!since.is_modified(at(0, 500_000_000)));
+        assert!(since.is_modified(at(1, 0)));
+    }
+}
@@ -0,0 +1,242 @@
+//! Changing pages from scripts and other programs with bearer tokens rather than the browser
+
+use std::sync::Arc;
+
+use comrak::plugins::syntect::SyntectAdapter;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::content::RawJson;
+use rocket::{Catcher, Request, Route, State};
+use serde::Deserialize;
+
+use crate::history;
+use crate::page::{self, Format, Loaded, Page, SaveError};
+use crate::settings::Settings;
+use crate::string_ext::StringExt;
+use crate::templates::page as page_templates;
+use crate::web::auth::ApiClient;
+use crate::web::IfUnmodifiedSince;
+use crate::PkbError;
+
+pub fn routes() -> Vec<Route> {
+    routes![put, delete]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![unauthorized, error]
+}
+
+/// A page submitted as `application/json`
+#[derive(Deserialize)]
+struct PageJson {
+    /// The front-matter, E.g. `{"title": "Example", "tags": ["example"]}`
+    #[serde(default)]
+    metadata: serde_json::Value,
+    body: String,
+}
+
+#[derive(Responder)]
+pub(crate) enum ApiResponse {
+    Updated(RawJson<String>),
+    #[response(status = 201)]
+    Created(RawJson<String>, Header<'static>),
+    #[response(status = 204)]
+    Deleted(()),
+    /// The request body can't be used, E.g. the front-matter is invalid
+    Rejected((Status, RawJson<String>)),
+}
+
+/// Response asking the client for a token
+#[derive(Responder)]
+#[response(status = 401)]
+pub(crate) struct TokenChallenge {
+    body: RawJson<String>,
+    www_authenticate: Header<'static>,
+}
+
+/// Create or replace a page with Markdown, either the complete source as `text/markdown` or the
+/// front-matter and body as JSON
+#[put("/api/pages/<name>", data = "<data>")]
+pub(crate) async fn put(
+    name: &str,
+    data: Data<'_>,
+    content_type: Option<&ContentType>,
+    limits: &Limits,
+    settings: &State<Settings>,
+    adapter: &State<Arc<SyntectAdapter>>,
+    client: ApiClient,
+    if_unmodified_since: Option<IfUnmodifiedSince>,
+) -> Result<ApiResponse, PkbError> {
+    let limit = limits.get("page").unwrap_or_else(|| 1.mebibytes());
+    let body = data.open(limit).into_string().await?;
+    if !body.is_complete() {
+        return Ok(rejected(Status::PayloadTooLarge, "page is too large"));
+    }
+    let source = match content_type {
+        Some(content_type) if content_type.is_json() => {
+            match serde_json::from_str::<PageJson>(&body)
+                .map_err(|err| err.to_string())
+                .and_then(|json| {
+                    page::page_source(&json.metadata, &json.body).map_err(|err| err.to_string())
+                }) {
+                Ok(source) => source,
+                Err(err) => return Ok(invalid(err)),
+            }
+        }
+        Some(content_type) if is_markdown(content_type) => body.into_inner(),
+        _ => {
+            return Ok(rejected(
+                Status::UnsupportedMediaType,
+                "expected application/json or text/markdown",
+            ))
+        }
+    };
+    // The same validation as when the page is viewed, so invalid pages are never saved
+    if let Err(err) = page::Draft::parse(&source) {
+        return Ok(invalid(err.to_string()));
+    }
+
+    match Page::find(name, &settings.pages_path) {
+        Some(existing) => {
+            let existing = existing.load()?;
+            // Org-mode and plain text pages don't have front-matter, so replacing them with
+            // Markdown would mangle the page
+            if existing.format() != Format::Markdown {
+                return Ok(rejected(
+                    Status::UnprocessableEntity,
+                    "only Markdown pages can be replaced",
+                ));
+            }
+            check_unmodified(&existing, if_unmodified_since)?;
+            existing.save(&source, existing.mtime())?;
+            let message = format!("Update {} via the API", existing.name);
+            history::record(settings, existing.path(), &client.name, &message);
+            info!("{} updated {} via the API", client.name, existing.name);
+
+            let page = reload(&existing.name, settings)?;
+            Ok(ApiResponse::Updated(page_json(&page, settings, adapter)))
+        }
+        None => {
+            let slug = name.to_slug();
+            let created = match Page::create_from_source(&slug, &source, &settings.pages_path) {
+                Ok(created) => created,
+                Err(SaveError::InvalidName(reason)) => {
+                    return Ok(rejected(
+                        Status::UnprocessableEntity,
+                        &format!("the name {}", reason),
+                    ))
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let message = format!("Create {} via the API", slug);
+            history::record(settings, created.path(), &client.name, &message);
+            info!("{} created {} via the API", client.name, slug);
+
+            let page = created.load()?;
+            let location = Header::new("Location", uri!(put(name = &page.slug())).to_string());
+            Ok(ApiResponse::Created(
+                page_json(&page, settings, adapter),
+                location,
+            ))
+        }
+    }
+}
+
+#[delete("/api/pages/<name>")]
+pub(crate) fn delete(
+    name: &str,
+    settings: &State<Settings>,
+    client: ApiClient,
+    if_unmodified_since: Option<IfUnmodifiedSince>,
+) -> Result<ApiResponse, PkbError> {
+    let page = Page::find(name, &settings.pages_path)
+        .ok_or(PkbError::PageNotFound)?
+        .load()?;
+    check_unmodified(&page, if_unmodified_since)?;
+    page.delete(page.mtime())?;
+    let message = format!("Delete {} via the API", page.name);
+    history::record(settings, page.path(), &client.name, &message);
+    info!("{} deleted {} via the API", client.name, page.name);
+    Ok(ApiResponse::Deleted(()))
+}
+
+#[catch(401)]
+fn unauthorized(req: &Request<'_>) -> TokenChallenge {
+    let realm = req
+        .rocket()
+        .state::<Settings>()
+        .map_or("pkb", |settings| settings.name.as_str());
+    TokenChallenge {
+        body: error_json(Status::Unauthorized, "a valid bearer token is required"),
+        www_authenticate: Header::new(
+            "WWW-Authenticate",
+            format!("Bearer realm=\"{}\"", realm.replace('"', "")),
+        ),
+    }
+}
+
+#[catch(default)]
+fn error(status: Status, _req: &Request<'_>) -> (Status, RawJson<String>) {
+    (
+        status,
+        error_json(status, status.reason().unwrap_or("error")),
+    )
+}
+
+// Rejects the change if the client's copy of the page is out of date. Pages that don't exist yet
+// have nothing to compare against so are always created.
+fn check_unmodified(
+    page: &Page<Loaded>,
+    if_unmodified_since: Option<IfUnmodifiedSince>,
+) -> Result<(), PkbError> {
+    match if_unmodified_since {
+        Some(since) if since.is_modified(page.mtime()) => Err(PkbError::PreconditionFailed),
+        Some(_) | None => Ok(()),
+    }
+}
+
+fn reload(name: &str, settings: &Settings) -> Result<Page<Loaded>, PkbError> {
+    Ok(Page::find(name, &settings.pages_path)
+        .ok_or(PkbError::PageNotFound)?
+        .load()?)
+}
+
+fn is_markdown(content_type: &ContentType) -> bool {
+    content_type.top() == "text"
+        && (content_type.sub() == "markdown" || content_type.sub() == "plain")
+}
+
+fn page_json(
+    page: &Page<Loaded>,
+    settings: &Settings,
+    adapter: &SyntectAdapter,
+) -> RawJson<String> {
+    let json = serde_json::json!({
+        "name": page.name,
+        "title": page.title(),
+        "tags": page.tags(),
+        "url": page_templates::canonical_url(page, settings),
+        "modified": page.mtime_rfc3339(),
+        "html": page_templates::render(page, settings, adapter),
+    });
+    RawJson(json.to_string())
+}
+
+fn rejected(status: Status, message: &str) -> ApiResponse {
+    ApiResponse::Rejected((status, error_json(status, message)))
+}
+
+fn invalid(message: String) -> ApiResponse {
+    rejected(
+        Status::UnprocessableEntity,
+        &format!("invalid front-matter: {}", message),
+    )
+}
+
+fn error_json(status: Status, message: &str) -> RawJson<String> {
+    let json = serde_json::json!({
+        "status": status.code,
+        "error": message,
+    });
+    RawJson(json.to_string())
+}
@@ -0,0 +1,395 @@
+//! Authentication of the users that can view private pages, either with HTTP Basic
+//! authentication or by a trusted reverse proxy, and of API clients with bearer tokens
+
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::content::RawHtml;
+use rocket::tokio::task;
+use rocket::Request;
+use sha2::{Digest, Sha256};
+
+use crate::page::Audience;
+use crate::settings::Settings;
+use crate::web::CachePolicy;
+use crate::PkbError;
+
+/// The user making the request, if they supplied valid credentials or were authenticated by a
+/// trusted proxy
+pub(crate) struct Viewer {
+    user: Option<String>,
+    /// Groups the user is a member of, only supplied by proxies
+    groups: Vec<String>,
+}
+
+/// Rejects form submissions from other sites. Browsers send HTTP Basic credentials with
+/// requests from any site, so without this another site could make changes as the user.
+pub(crate) struct SameOrigin;
+
+/// A client of the API, authenticated with one of the configured bearer tokens
+pub(crate) struct ApiClient {
+    pub(crate) name: String,
+}
+
+/// Response asking the client for credentials
+#[derive(Responder)]
+#[response(status = 401)]
+pub(crate) struct Challenge {
+    body: RawHtml<&'static str>,
+    www_authenticate: Header<'static>,
+}
+
+impl Viewer {
+    pub(crate) fn is_authenticated(&self) -> bool {
+        self.user.is_some()
+    }
+
+    pub(crate) fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// The name of the user, or Unauthorized for anonymous viewers
+    pub(crate) fn require_user(&self) -> Result<&str, PkbError> {
+        self.user().ok_or(PkbError::Unauthorized)
+    }
+
+    pub(crate) fn audience<'a>(&'a self, settings: &'a Settings) -> Audience<'a> {
+        Audience::new(settings, self.is_authenticated(), &self.groups)
+    }
+
+    /// The cache policy for a private page or file, which anonymous users are not allowed to see
+    pub(crate) fn private_policy(&self, policy: CachePolicy) -> Result<CachePolicy, PkbError> {
+        if self.is_authenticated() {
+            Ok(policy.private())
+        } else {
+            Err(PkbError::Unauthorized)
+        }
+    }
+
+    /// The cache policy for a response that varies by user, like a list of pages that may
+    /// include private pages. Shared caches must not store the responses of authenticated users.
+    pub(crate) fn varying_policy(&self, policy: CachePolicy) -> CachePolicy {
+        let policy = policy.varies_by_user();
+        if self.is_authenticated() {
+            policy.private()
+        } else {
+            policy
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let anonymous = Viewer {
+            user: None,
+            groups: Vec::new(),
+        };
+        let settings = match req.rocket().state::<Settings>() {
+            Some(settings) => settings,
+            None => return Outcome::Success(anonymous),
+        };
+        if let Some(viewer) = forwarded_viewer(req, settings) {
+            return Outcome::Success(viewer);
+        }
+
+        // Invalid credentials are treated like none at all, so private pages ask for them again
+        let authorization = match req.headers().get_one("authorization") {
+            Some(authorization) => authorization,
+            None => return Outcome::Success(anonymous),
+        };
+        let (user, password) = match basic_credentials(authorization) {
+            Some(credentials) => credentials,
+            None => return Outcome::Success(anonymous),
+        };
+        let verified = req.rocket().state::<VerifiedCredentials>();
+        if verified.map_or(false, |verified| verified.contains(authorization)) {
+            return Outcome::Success(Viewer {
+                user: Some(user),
+                ..anonymous
+            });
+        }
+
+        // Hashing the password takes long enough to hold up other requests on this thread
+        let hash = settings.auth.users.get(&user).cloned();
+        let name = user.clone();
+        let is_valid = task::spawn_blocking(move || verify(&name, hash.as_deref(), &password))
+            .await
+            .unwrap_or(false);
+        if !is_valid {
+            return Outcome::Success(anonymous);
+        }
+        if let Some(verified) = verified {
+            verified.insert(authorization);
+        }
+        Outcome::Success(Viewer {
+            user: Some(user),
+            ..anonymous
+        })
+    }
+}
+
+/// Digests of the `Authorization` headers with valid credentials, so that the password of each
+/// user is only hashed once rather than on every request
+#[derive(Default)]
+pub(crate) struct VerifiedCredentials(Mutex<HashSet<[u8; 32]>>);
+
+impl VerifiedCredentials {
+    /// Maximum number of verified headers remembered
+    const LIMIT: usize = 64;
+
+    fn contains(&self, authorization: &str) -> bool {
+        self.lock().contains(&Self::digest(authorization))
+    }
+
+    fn insert(&self, authorization: &str) {
+        let mut verified = self.lock();
+        if verified.len() >= Self::LIMIT {
+            verified.clear();
+        }
+        verified.insert(Self::digest(authorization));
+    }
+
+    // The headers contain the passwords so aren't kept themselves
+    fn digest(authorization: &str) -> [u8; 32] {
+        Sha256::digest(authorization.as_bytes()).into()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashSet<[u8; 32]>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// The user authenticated by the proxy the request came through. The headers are ignored unless
+// the request came directly from a trusted proxy, as anyone else could set them.
+fn forwarded_viewer(req: &Request<'_>, settings: &Settings) -> Option<Viewer> {
+    // Not client_ip(), which trusts the X-Real-IP header
+    let peer = req.remote()?.ip();
+    if !settings
+        .auth
+        .trusted_proxies
+        .iter()
+        .any(|range| range.contains(peer))
+    {
+        return None;
+    }
+
+    let user = req
+        .headers()
+        .get_one("x-forwarded-user")
+        .map(str::trim)
+        .filter(|user| !user.is_empty())?;
+    let groups = req
+        .headers()
+        .get("x-forwarded-groups")
+        .flat_map(|groups| groups.split(','))
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(String::from)
+        .collect();
+    Some(Viewer {
+        user: Some(user.to_string()),
+        groups,
+    })
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SameOrigin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        if is_cross_site(
+            headers.get_one("sec-fetch-site"),
+            headers.get_one("origin"),
+            headers.get_one("host"),
+        ) {
+            warn!("{}: rejected cross-site request", req.uri());
+            Outcome::Error((Status::Forbidden, ()))
+        } else {
+            Outcome::Success(SameOrigin)
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiClient {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let name = req.rocket().state::<Settings>().and_then(|settings| {
+            let token = req
+                .headers()
+                .get_one("authorization")
+                .and_then(bearer_token)?;
+            settings
+                .auth
+                .api_tokens
+                .iter()
+                .find(|(_, expected)| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+                .map(|(name, _)| name.clone())
+        });
+        match name {
+            Some(name) => Outcome::Success(ApiClient { name }),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+impl Challenge {
+    pub(crate) fn new(settings: Option<&Settings>) -> Self {
+        const BODY: &str = include_str!("../templates/401.html");
+        let realm = settings.map_or("pkb", |settings| settings.name.as_str());
+        Challenge {
+            body: RawHtml(BODY),
+            www_authenticate: Header::new(
+                "WWW-Authenticate",
+                format!(
+                    "Basic realm=\"{}\", charset=\"UTF-8\"",
+                    realm.replace('"', "")
+                ),
+            ),
+        }
+    }
+}
+
+// Browsers send Sec-Fetch-Site, older ones only send Origin. Requests without either, like
+// those from scripts, are not from a browser so are allowed.
+fn is_cross_site(sec_fetch_site: Option<&str>, origin: Option<&str>, host: Option<&str>) -> bool {
+    match (sec_fetch_site, origin) {
+        (Some(site), _) => !matches!(site, "same-origin" | "none"),
+        (None, Some(origin)) => origin
+            .split_once("://")
+            .map_or(true, |(_, origin_host)| Some(origin_host) != host),
+        (None, None) => false,
+    }
+}
+
+// Authorization: Basic base64(user:password)
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+// Authorization: Bearer <token>
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+// Compares every byte so the time taken doesn't reveal how much of a token was guessed
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Check the password against the user's hash. Unknown users, with no hash, are checked against
+// a dummy hash so that the time taken doesn't reveal which users exist.
+fn verify(user: &str, hash: Option<&str>, password: &str) -> bool {
+    let (hash, is_known) = match hash {
+        Some(hash) => (hash, true),
+        None => (dummy_hash(), false),
+    };
+    match PasswordHash::new(hash) {
+        Ok(hash) => {
+            let is_valid = Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok();
+            is_valid && is_known
+        }
+        Err(err) => {
+            error!("invalid password hash for user '{}': {}", user, err);
+            false
+        }
+    }
+}
+
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let salt = SaltString::encode_b64(b"pkb-unknown-user").expect("valid salt");
+        Argon2::default()
+            .hash_password(b"", &salt)
+            .map(|hash| hash.to_string())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_basic_credentials() {
+        // user:pass word
+        assert_eq!(
+            basic_credentials("Basic dXNlcjpwYXNzIHdvcmQ="),
+            Some((String::from("user"), String::from("pass word")))
+        );
+        assert_eq!(
+            basic_credentials("basic dXNlcjpwYXNzIHdvcmQ="),
+            Some((String::from("user"), String::from("pass word")))
+        );
+        assert_eq!(basic_credentials("Bearer dXNlcjpwYXNz"), None);
+        assert_eq!(basic_credentials("Basic not-base64!"), None);
+        // No colon
+        assert_eq!(basic_credentials("Basic dXNlcg=="), None);
+    }
+
+    #[test]
+    fn parse_bearer_token() {
+        assert_eq!(bearer_token("Bearer secret"), Some("secret"));
+        assert_eq!(bearer_token("bearer  secret "), Some("secret"));
+        assert_eq!(bearer_token("Bearer "), None);
+        assert_eq!(bearer_token("Basic dXNlcjpwYXNz"), None);
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+
+    #[test]
+    fn verify_passwords() {
+        let salt = SaltString::encode_b64(b"test salt").unwrap();
+        let hash = Argon2::default()
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string();
+        assert!(verify("user", Some(&hash), "secret"));
+        assert!(!verify("user", Some(&hash), "wrong"));
+        // The dummy hash is of an empty password, which must not let unknown users in
+        assert!(!verify("unknown", None, ""));
+
+        let verified = VerifiedCredentials::default();
+        assert!(!verified.contains("Basic dXNlcjpzZWNyZXQ="));
+        verified.insert("Basic dXNlcjpzZWNyZXQ=");
+        assert!(verified.contains("Basic dXNlcjpzZWNyZXQ="));
+        assert!(!verified.contains("Basic dXNlcjp3cm9uZw=="));
+    }
+
+    #[test]
+    fn cross_site_requests() {
+        let host = Some("example.com");
+        assert!(!is_cross_site(Some("same-origin"), None, host));
+        assert!(is_cross_site(Some("cross-site"), None, host));
+        assert!(is_cross_site(Some("same-site"), None, host));
+        assert!(!is_cross_site(None, Some("https://example.com"), host));
+        assert!(is_cross_site(None, Some("https://evil.example"), host));
+        assert!(is_cross_site(None, Some("null"), host));
+        assert!(!is_cross_site(None, None, host));
+    }
+}
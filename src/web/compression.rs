@@ -0,0 +1,290 @@
+use std::convert::Infallible;
+use std::io::{self, Cursor, Write};
+
+use flate2::write::GzEncoder;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, Response};
+
+use crate::web::etag_matches;
+
+/// Responses smaller than this aren't worth compressing
+const MIN_COMPRESS_SIZE: usize = 1024;
+
+/// Brotli quality used for dynamic responses. The maximum, 11, is too slow to use per request.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Content codings supported by `Compression`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+/// Compresses text responses with the best encoding the client accepts
+pub(crate) struct Compression;
+
+/// The encodings accepted by the client from its `Accept-Encoding` header, most preferred first
+pub(crate) struct AcceptEncoding(pub Vec<Encoding>);
+
+impl Encoding {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// File extension of pre-compressed files in this encoding
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    let mut writer =
+                        brotli::CompressorWriter::new(&mut compressed, 4096, BROTLI_QUALITY, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(compressed)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+impl AcceptEncoding {
+    fn parse(accept_encoding: &str) -> Self {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut any = None;
+        for coding in accept_encoding.split(',') {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match name.to_ascii_lowercase().as_str() {
+                "br" => brotli = Some(quality),
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "*" => any = Some(quality),
+                _ => {}
+            }
+        }
+
+        // Prefer brotli when the client has no preference
+        let mut encodings = [
+            (Encoding::Brotli, brotli.or(any).unwrap_or(0.0)),
+            (Encoding::Gzip, gzip.or(any).unwrap_or(0.0)),
+        ]
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+        encodings.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        AcceptEncoding(
+            encodings
+                .into_iter()
+                .map(|(encoding, _)| encoding)
+                .collect(),
+        )
+    }
+
+    fn preferred(&self) -> Option<Encoding> {
+        self.0.first().copied()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptEncoding {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let accept_encoding = req.headers().get_one("accept-encoding").unwrap_or_default();
+        Outcome::Success(AcceptEncoding::parse(accept_encoding))
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status() == Status::NotModified {
+            // Not modified responses carry the Vary header of the full response they stand in
+            // for. They have no content type so it's determined from the request path.
+            if response_content_type(request).map_or(false, is_compressible) {
+                response.adjoin_raw_header("Vary", "Accept-Encoding");
+            }
+            return;
+        }
+        if response.status() != Status::Ok
+            || response.headers().contains("content-encoding")
+            || !response.content_type().map_or(false, is_compressible)
+        {
+            return;
+        }
+        // The response varies whether it ends up compressed or not
+        response.adjoin_raw_header("Vary", "Accept-Encoding");
+
+        let encoding = match AcceptEncoding::parse(
+            request
+                .headers()
+                .get_one("accept-encoding")
+                .unwrap_or_default(),
+        )
+        .preferred()
+        {
+            Some(encoding) => encoding,
+            None => return,
+        };
+        if matches!(response.body().preset_size(), Some(size) if size < MIN_COMPRESS_SIZE) {
+            return;
+        }
+
+        // The compressed response is a different representation so needs its own entity tag
+        let etag = response
+            .headers()
+            .get_one("etag")
+            .and_then(|etag| etag.strip_suffix('"'))
+            .map(|etag| format!("{}-{}\"", etag, encoding.name()));
+        if let Some(etag) = &etag {
+            response.set_raw_header("ETag", etag.clone());
+            // Don't bother compressing responses the IfNoneMatch fairing will discard
+            let is_not_modified = request
+                .headers()
+                .get_one("if-none-match")
+                .map_or(false, |if_none_match| etag_matches(if_none_match, etag));
+            if is_not_modified {
+                return;
+            }
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                error!("{}: unable to read response body: {}", request.uri(), err);
+                response.set_status(Status::InternalServerError);
+                response.set_sized_body(0, Cursor::new(Vec::new()));
+                return;
+            }
+        };
+        let body = if body.len() < MIN_COMPRESS_SIZE {
+            body
+        } else {
+            match encoding.compress(&body) {
+                Ok(compressed) => {
+                    response.set_raw_header("Content-Encoding", encoding.name());
+                    compressed
+                }
+                Err(err) => {
+                    error!("{}: unable to compress response: {}", request.uri(), err);
+                    body
+                }
+            }
+        };
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// The content type of the full response to a request, from the extension of the path. Paths
+// without an extension are pages.
+fn response_content_type(request: &Request<'_>) -> Option<ContentType> {
+    let name = request.uri().path().segments().last().unwrap_or_default();
+    match name.rsplit_once('.') {
+        Some((_, extension)) => ContentType::from_extension(extension),
+        None => Some(ContentType::HTML),
+    }
+}
+
+fn is_compressible(content_type: ContentType) -> bool {
+    content_type.is_html()
+        || content_type.is_xml()
+        || content_type.is_css()
+        || content_type.is_json()
+        || content_type.is_svg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn parse_accept_encoding() {
+        let parse = |value: &str| AcceptEncoding::parse(value).0;
+        assert_eq!(
+            parse("gzip, deflate, br"),
+            [Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(
+            parse("gzip;q=1.0, br;q=0.5"),
+            [Encoding::Gzip, Encoding::Brotli]
+        );
+        assert_eq!(parse("br;q=0, gzip"), [Encoding::Gzip]);
+        assert_eq!(parse("*"), [Encoding::Brotli, Encoding::Gzip]);
+        assert!(parse("identity").is_empty());
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        use std::io::Read;
+
+        let data = "<p>Hello, world!</p>".repeat(100);
+        let gzipped = Encoding::Gzip.compress(data.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(gzipped.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let compressed = Encoding::Brotli.compress(data.as_bytes()).unwrap();
+        assert!(compressed.len() < data.len());
+        let mut decoded = String::new();
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[get("/<_path..>")]
+    fn not_modified(_path: std::path::PathBuf) -> Status {
+        Status::NotModified
+    }
+
+    #[test]
+    fn not_modified_varies() {
+        let rocket = rocket::build()
+            .mount("/", routes![not_modified])
+            .attach(Compression);
+        let client = Client::untracked(rocket).unwrap();
+        let vary = |uri: &str| {
+            let response = client.get(uri.to_string()).dispatch();
+            response.headers().get_one("vary").map(ToString::to_string)
+        };
+
+        assert_eq!(vary("/some-page").as_deref(), Some("Accept-Encoding"));
+        assert_eq!(
+            vary("/public/css/style.css").as_deref(),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(vary("/files/photo.png"), None);
+    }
+}
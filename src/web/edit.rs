@@ -0,0 +1,319 @@
+//! Creating and editing pages in the browser
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use comrak::plugins::syntect::SyntectAdapter;
+use rocket::form::Form;
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use rocket::{Route, State};
+use time::OffsetDateTime;
+
+use crate::history;
+use crate::page::{Draft, Format, Loaded, Page, SaveError};
+use crate::settings::Settings;
+use crate::templates::edit::{Edit, Head, NewPage, Preview};
+use crate::templates::page as page_templates;
+use crate::templates::{Layout, Nil};
+use crate::web::auth::{SameOrigin, Viewer};
+use crate::{web, PkbError};
+
+pub fn routes() -> Vec<Route> {
+    routes![edit, save, preview, new_page, create]
+}
+
+#[derive(FromForm)]
+pub(crate) struct EditForm {
+    source: String,
+    /// Modification time of the page when it was loaded for editing, in nanoseconds
+    mtime: u64,
+    /// Optional description of the change, used as the commit message
+    summary: String,
+}
+
+#[derive(FromForm)]
+pub(crate) struct PreviewForm {
+    source: String,
+}
+
+#[derive(FromForm)]
+pub(crate) struct NewPageForm {
+    title: String,
+    /// Comma separated
+    tags: String,
+    body: String,
+    summary: String,
+}
+
+#[derive(Responder)]
+pub(crate) enum SaveResponse {
+    Saved(Redirect),
+    /// The page was changed by someone else while it was being edited
+    #[response(status = 409)]
+    Conflict(RawHtml<String>),
+    /// Only Markdown pages with valid front-matter can be saved
+    #[response(status = 422)]
+    Invalid(RawHtml<String>),
+}
+
+/// Org-mode and plain text pages don't have front-matter, so saving the editor's Markdown to
+/// them would mangle the page
+const NOT_MARKDOWN: &str = "Only Markdown pages can be edited in the browser.";
+
+// Ranked below the routes with a static first segment, like /tags/<name>, which would
+// otherwise collide with this one
+#[get("/<name>/edit", rank = 3)]
+pub(crate) fn edit(
+    name: &str,
+    settings: &State<Settings>,
+    viewer: Viewer,
+) -> Result<RawHtml<String>, PkbError> {
+    let page = load_for_editing(name, settings, &viewer)?;
+    let error = (page.format() != Format::Markdown).then_some(NOT_MARKDOWN);
+    Ok(edit_page(settings, &page, page.source(), false, error))
+}
+
+#[post("/<name>/edit", data = "<form>", rank = 3)]
+pub(crate) fn save(
+    name: &str,
+    form: Form<EditForm>,
+    settings: &State<Settings>,
+    viewer: Viewer,
+    _same_origin: SameOrigin,
+) -> Result<SaveResponse, PkbError> {
+    let page = load_for_editing(name, settings, &viewer)?;
+    // Browsers submit textareas with CRLF line endings
+    let source = form.source.replace("\r\n", "\n");
+    if page.format() != Format::Markdown {
+        return Ok(SaveResponse::Invalid(edit_page(
+            settings,
+            &page,
+            &source,
+            false,
+            Some(NOT_MARKDOWN),
+        )));
+    }
+    // Saving invalid front-matter would break the page, so the editor is shown again instead
+    if let Err(err) = Draft::parse(&source) {
+        let error = format!("invalid front-matter: {}", err);
+        return Ok(SaveResponse::Invalid(edit_page(
+            settings,
+            &page,
+            &source,
+            false,
+            Some(error.as_str()),
+        )));
+    }
+    match page.save(&source, from_nanos(form.mtime)) {
+        Ok(()) => {
+            let user = viewer.require_user()?;
+            info!("{} saved {}", user, page.name);
+            let message = commit_message(&form.summary, || format!("Edit {}", page.title()));
+            history::record(settings, page.path(), user, &message);
+            Ok(SaveResponse::Saved(Redirect::to(uri!(web::page::show(
+                name = &page.slug()
+            )))))
+        }
+        Err(SaveError::Conflict) => Ok(SaveResponse::Conflict(edit_page(
+            settings, &page, &source, true, None,
+        ))),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Responder)]
+pub(crate) enum CreateResponse {
+    Created(Redirect),
+    /// The title has no slug, is reserved, or a page with the same slug exists
+    #[response(status = 422)]
+    Invalid(RawHtml<String>),
+}
+
+#[get("/pages/new")]
+pub(crate) fn new_page(
+    settings: &State<Settings>,
+    viewer: Viewer,
+) -> Result<RawHtml<String>, PkbError> {
+    viewer.require_user()?;
+    Ok(new_page_form(settings, "", "", "", None))
+}
+
+#[post("/pages/new", data = "<form>")]
+pub(crate) fn create(
+    form: Form<NewPageForm>,
+    settings: &State<Settings>,
+    viewer: Viewer,
+    _same_origin: SameOrigin,
+) -> Result<CreateResponse, PkbError> {
+    let user = viewer.require_user()?;
+    let tags = form
+        .tags
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let body = form.body.replace("\r\n", "\n");
+    let created = OffsetDateTime::now_utc().date();
+    let error = match Page::create(
+        form.title.trim(),
+        &tags,
+        &body,
+        created,
+        &settings.pages_path,
+    ) {
+        Ok(page) => {
+            info!("{} created {}", user, page.name);
+            let message = commit_message(&form.summary, || format!("Create {}", form.title.trim()));
+            history::record(settings, page.path(), user, &message);
+            return Ok(CreateResponse::Created(Redirect::to(uri!(
+                web::page::show(name = &page.slug())
+            ))));
+        }
+        Err(SaveError::Exists) => String::from("A page with the same name already exists."),
+        Err(SaveError::InvalidName(reason)) => format!("The title {}.", reason),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(CreateResponse::Invalid(new_page_form(
+        settings,
+        &form.title,
+        &form.tags,
+        &form.body,
+        Some(error.as_str()),
+    )))
+}
+
+/// Render Markdown submitted from the editor
+#[post("/preview", data = "<form>")]
+pub(crate) fn preview(
+    form: Form<PreviewForm>,
+    settings: &State<Settings>,
+    adapter: &State<Arc<SyntectAdapter>>,
+    viewer: Viewer,
+    _same_origin: SameOrigin,
+) -> Result<RawHtml<String>, PkbError> {
+    viewer.require_user()?;
+    let content = Draft::parse(&form.source)
+        .map(|draft| page_templates::render_draft(&draft, settings, adapter))
+        .map_err(|err| format!("invalid front-matter: {}", err));
+    Ok(crate::html(Preview { content }))
+}
+
+// Only authenticated users that can view a page can edit it
+fn load_for_editing(
+    name: &str,
+    settings: &Settings,
+    viewer: &Viewer,
+) -> Result<Page<Loaded>, PkbError> {
+    viewer.require_user()?;
+    let page = Page::find(name, &settings.pages_path)
+        .ok_or(PkbError::PageNotFound)?
+        .load()?;
+    if page.is_visible_to(&viewer.audience(settings)) {
+        Ok(page)
+    } else {
+        Err(PkbError::PageNotFound)
+    }
+}
+
+fn edit_page(
+    settings: &Settings,
+    page: &Page<Loaded>,
+    source: &str,
+    conflict: bool,
+    error: Option<&str>,
+) -> RawHtml<String> {
+    let title = format!("Editing {}", page.title());
+    crate::html(Layout {
+        settings,
+        title: &title,
+        head: Head {},
+        body: Edit {
+            page,
+            source,
+            mtime: to_nanos(page.mtime()),
+            conflict,
+            error,
+        },
+    })
+}
+
+fn new_page_form(
+    settings: &Settings,
+    title: &str,
+    tags: &str,
+    body: &str,
+    error: Option<&str>,
+) -> RawHtml<String> {
+    crate::html(Layout {
+        settings,
+        title: "New page",
+        head: Nil {},
+        body: NewPage {
+            title,
+            tags,
+            body,
+            error,
+        },
+    })
+}
+
+fn commit_message(summary: &str, default: impl FnOnce() -> String) -> String {
+    let summary = summary.trim();
+    if summary.is_empty() {
+        default()
+    } else {
+        summary.to_string()
+    }
+}
+
+fn to_nanos(mtime: SystemTime) -> u64 {
+    mtime
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use std::fs;
+
+    #[test]
+    fn save_rejects_invalid_front_matter() {
+        let dir = TempDir::new("edit");
+        let path = dir.path().join("page.md");
+        fs::write(&path, "Original").unwrap();
+        let mtime = to_nanos(fs::metadata(&path).unwrap().modified().unwrap());
+        // Authenticate with a trusted proxy rather than a password
+        let figment =
+            test_support::figment(dir.path()).merge(("auth.trusted_proxies", ["127.0.0.1"]));
+        let client = Client::untracked(web::rocket().configure(figment)).unwrap();
+
+        // ---\ntags: [x\n---
+        let response = client
+            .post("/page/edit")
+            .remote("127.0.0.1:8000".parse().unwrap())
+            .header(Header::new("X-Forwarded-User", "someone"))
+            .header(ContentType::Form)
+            .body(format!(
+                "source=---%0Atags%3A+%5Bx%0A---&mtime={}&summary=",
+                mtime
+            ))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .into_string()
+            .unwrap()
+            .contains("invalid front-matter"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Original");
+    }
+}
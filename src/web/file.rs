@@ -0,0 +1,220 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use rocket::fs::NamedFile;
+use rocket::http::ContentType;
+use rocket::response::{self, Responder};
+use rocket::{Request, Route, State};
+
+use crate::attachment::{self, RESPONSIVE_WIDTHS};
+use crate::settings::{CacheClass, Settings};
+use crate::web::auth::Viewer;
+use crate::web::compression::{AcceptEncoding, Encoding};
+use crate::web::{CachePolicy, CachedFile, IfModifiedSince};
+use crate::{return_if_fresh, PkbError};
+
+/// File extensions that may be served as attachments. Notably this excludes the page formats so
+/// that the source of pages (including hidden ones) is not exposed.
+const ALLOWED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "pdf", "csv", "mp3", "mp4", "webm", "ogg",
+];
+
+/// A file from the attachments or public directory
+pub(crate) struct Attachment {
+    file: NamedFile,
+    content_type: ContentType,
+    /// Encoding of a pre-compressed file
+    encoding: Option<Encoding>,
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![show, resized]
+}
+
+#[get("/files/<path..>")]
+pub(crate) async fn show(
+    path: PathBuf,
+    settings: &State<Settings>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedFile, PkbError> {
+    let content_type = content_type(&path).ok_or(PkbError::PageNotFound)?;
+    let policy = attachment_policy(&path, settings, &viewer)?;
+
+    let full_path = settings.attachments().join(&path);
+    let meta = fs::metadata(&full_path)
+        .ok()
+        .filter(|meta| meta.is_file())
+        .ok_or(PkbError::PageNotFound)?;
+    let last_modified = meta.modified()?;
+    return_if_fresh!(modified_since, last_modified, policy);
+
+    let file = NamedFile::open(&full_path).await?;
+    Ok(CachedFile::file(
+        policy,
+        last_modified,
+        meta.len(),
+        Attachment {
+            file,
+            content_type,
+            encoding: None,
+        },
+    ))
+}
+
+/// Serve a copy of an image attachment resized to one of the `RESPONSIVE_WIDTHS`
+#[get("/resized/<width>/<path..>")]
+pub(crate) async fn resized(
+    width: u32,
+    path: PathBuf,
+    settings: &State<Settings>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedFile, PkbError> {
+    if !RESPONSIVE_WIDTHS.contains(&width) || !attachment::is_resizable(&path) {
+        return Err(PkbError::PageNotFound);
+    }
+    let content_type = content_type(&path).ok_or(PkbError::PageNotFound)?;
+    let policy = attachment_policy(&path, settings, &viewer)?;
+
+    let source = settings.attachments().join(&path);
+    let meta = fs::metadata(&source)
+        .ok()
+        .filter(|meta| meta.is_file())
+        .ok_or(PkbError::PageNotFound)?;
+    let last_modified = meta.modified()?;
+    return_if_fresh!(modified_since, last_modified, policy);
+
+    let cache_path = settings.image_cache().join(width.to_string()).join(&path);
+    let resized_path = cache_path.clone();
+    rocket::tokio::task::spawn_blocking(move || attachment::resize(&source, width, &resized_path))
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))??;
+
+    // The resized copy is never older than the source, and is only replaced when the source
+    // changes, so its metadata identifies this version of the resized image
+    let cached = fs::metadata(&cache_path)?;
+    let file = NamedFile::open(&cache_path).await?;
+    Ok(CachedFile::file(
+        policy,
+        cached.modified()?,
+        cached.len(),
+        Attachment {
+            file,
+            content_type,
+            encoding: None,
+        },
+    ))
+}
+
+/// Serve the static assets in the `public` directory.
+///
+/// Pre-compressed copies of a file with a `.br` or `.gz` extension added, E.g. `style.css.br`,
+/// are served instead of the file to clients that accept that encoding.
+#[get("/public/<path..>")]
+pub(crate) async fn public(
+    path: PathBuf,
+    settings: &State<Settings>,
+    accept_encoding: AcceptEncoding,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedFile, PkbError> {
+    let policy = CachePolicy::new(settings, CacheClass::Assets);
+    let full_path = Path::new("public").join(&path);
+    let meta = fs::metadata(&full_path)
+        .ok()
+        .filter(|meta| meta.is_file())
+        .ok_or(PkbError::PageNotFound)?;
+    let last_modified = meta.modified()?;
+    return_if_fresh!(modified_since, last_modified, policy);
+
+    let content_type = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+    let (full_path, meta, encoding) = accept_encoding
+        .0
+        .iter()
+        .find_map(|&encoding| {
+            let compressed_path = with_added_extension(&full_path, encoding.extension());
+            fs::metadata(&compressed_path)
+                .ok()
+                // Ignore copies that weren't updated with the file
+                .filter(|compressed| {
+                    compressed
+                        .modified()
+                        .map_or(false, |mtime| mtime >= last_modified)
+                })
+                .map(|compressed| (compressed_path, compressed, Some(encoding)))
+        })
+        .unwrap_or((full_path, meta, None));
+
+    let file = NamedFile::open(&full_path).await?;
+    Ok(CachedFile::file(
+        policy,
+        last_modified,
+        meta.len(),
+        Attachment {
+            file,
+            content_type,
+            encoding,
+        },
+    ))
+}
+
+fn with_added_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+fn content_type(path: &Path) -> Option<ContentType> {
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase)?;
+    ALLOWED_EXTENSIONS
+        .contains(&extension.as_str())
+        .then(|| ContentType::from_extension(&extension).unwrap_or(ContentType::Binary))
+}
+
+// Attachments in a folder named after a page are hidden along with the page, and are only
+// available to the users that can view the page when it is private
+fn attachment_policy(
+    path: &Path,
+    settings: &Settings,
+    viewer: &Viewer,
+) -> Result<CachePolicy, PkbError> {
+    let policy = CachePolicy::new(settings, CacheClass::Assets);
+    match attachment::owning_page(path, &settings.pages_path) {
+        Some(page) if page.is_hidden() => Err(PkbError::PageNotFound),
+        Some(page) if page.is_private(&settings.auth.private_tags) => {
+            let policy = viewer.private_policy(policy)?;
+            if page.is_visible_to(&viewer.audience(settings)) {
+                Ok(policy)
+            } else {
+                Err(PkbError::PageNotFound)
+            }
+        }
+        _ => Ok(policy),
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Attachment {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let is_svg = self.content_type == ContentType::SVG;
+        let mut response = self.file.respond_to(req)?;
+        response.set_header(self.content_type);
+        if let Some(encoding) = self.encoding {
+            response.set_raw_header("Content-Encoding", encoding.name());
+            response.adjoin_raw_header("Vary", "Accept-Encoding");
+        }
+        if is_svg {
+            // SVG can contain script, don't allow it to run in the context of the site
+            response.set_raw_header("Content-Security-Policy", "sandbox");
+        }
+        Ok(response)
+    }
+}
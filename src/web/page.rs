@@ -1,73 +1,157 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use comrak::plugins::syntect::SyntectAdapter;
-use rocket::fs::FileName;
+use rocket::response::Redirect;
 use rocket::{Route, State};
 
-use crate::page::Page;
-use crate::settings::Settings;
-use crate::templates::page::{Index, Show};
+use crate::page::{Format, Loaded, NotLoaded, Page};
+use crate::related::RelatedPages;
+use crate::render_cache::RenderCache;
+use crate::settings::{CacheClass, Settings};
+use crate::templates::page::{self as page_templates, Head, Index, Show};
 use crate::templates::{Layout, Nil};
-use crate::web::{CachedHtml, IfModifiedSince};
+use crate::web::auth::Viewer;
+use crate::web::{CachePolicy, CachedHtml, IfModifiedSince};
 use crate::{return_if_fresh, PkbError};
 
 pub fn routes() -> Vec<Route> {
     routes![index, show]
 }
 
+#[derive(Responder)]
+pub(crate) enum PageResponse {
+    Page(CachedHtml),
+    /// Permanent redirect to the canonical URL of the page
+    Redirect(Redirect),
+}
+
 #[get("/<name>", rank = 2)]
 pub(crate) fn show<'r>(
     name: &'r str,
     settings: &State<Settings>,
     adapter: &State<Arc<SyntectAdapter>>,
+    related: &State<RelatedPages>,
+    render_cache: &State<RenderCache>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<PageResponse, PkbError> {
+    let page = Page::find(name, &settings.pages_path).ok_or(PkbError::PageNotFound)?;
+    let slug = page.slug();
+    // Pages with names that have no slug, E.g. '---', can only be reached by their name
+    if !slug.is_empty() && name != slug {
+        // Only redirect viewers that can see the page, so the redirect doesn't reveal that a
+        // private page exists
+        page_policy(&page.load()?, settings, &viewer)?;
+        return Ok(PageResponse::Redirect(Redirect::moved(uri!(show(
+            name = &slug
+        )))));
+    }
+
+    show_page(
+        page,
+        settings,
+        adapter,
+        related,
+        render_cache,
+        viewer,
+        modified_since,
+    )
+    .map(PageResponse::Page)
+}
+
+pub(crate) fn show_page(
+    page: Page<NotLoaded>,
+    settings: &State<Settings>,
+    adapter: &State<Arc<SyntectAdapter>>,
+    related: &State<RelatedPages>,
+    render_cache: &State<RenderCache>,
+    viewer: Viewer,
     modified_since: Option<IfModifiedSince>,
 ) -> Result<CachedHtml, PkbError> {
-    let page = Page::new(FileName::new(name), &settings.pages_path)
-        .ok_or(PkbError::PageNotFound)?
-        .load()?;
-    return_if_fresh!(modified_since, page.last_modified(&settings.pages_path));
+    let page = page.load()?;
+    // Authenticated users get a link to edit the page
+    let policy = viewer.varying_policy(page_policy(&page, settings, &viewer)?);
+    let related = related.for_page(&page.name, settings);
+    let content_modified = page.last_modified(settings);
+    // The page changes when a new related page is added too
+    let last_modified = related
+        .iter()
+        .map(|related| related.mtime)
+        .fold(content_modified, SystemTime::max);
+    return_if_fresh!(modified_since, last_modified, policy);
 
-    let content = Layout {
+    let content = render_cache.get_or_render(&page.name, content_modified, || {
+        page_templates::render(&page, settings, adapter)
+    });
+    let description = page_templates::description(&page, &content);
+    let html = Layout {
         settings,
         title: &page.title(),
-        head: Nil {},
-        body: Show {
+        head: Head {
             page: &page,
             settings,
-            adapter: &*adapter,
+            description: description.as_deref(),
+        },
+        body: Show {
+            page: &page,
+            content: &content,
+            related: &related,
+            editable: viewer.is_authenticated() && page.format() == Format::Markdown,
         },
     };
-    Ok(CachedHtml::html(
-        page.last_modified(&settings.pages_path),
-        content.to_string(),
-    ))
+    Ok(CachedHtml::html(policy, last_modified, html.to_string()))
+}
+
+// The cache policy for a page the viewer is allowed to see
+fn page_policy(
+    page: &Page<Loaded>,
+    settings: &Settings,
+    viewer: &Viewer,
+) -> Result<CachePolicy, PkbError> {
+    let mut policy = CachePolicy::new(settings, CacheClass::Pages);
+    if page.is_private(&settings.auth.private_tags) {
+        policy = viewer.private_policy(policy)?;
+    }
+    // Pages restricted to other groups are not found, rather than revealing that they exist
+    if !page.is_visible_to(&viewer.audience(settings)) {
+        return Err(PkbError::PageNotFound);
+    }
+    Ok(policy)
 }
 
 #[get("/pages")]
 pub(crate) fn index<'r>(
     settings: &State<Settings>,
+    viewer: Viewer,
     modified_since: Option<IfModifiedSince>,
 ) -> Result<CachedHtml, PkbError> {
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Indexes));
     let mut pages = Page::all(&settings.pages_path);
     return_if_fresh!(
         modified_since,
-        Page::last_modified_page(&settings.pages_path)
+        Page::last_modified_page(&settings.pages_path),
+        policy
     );
 
     pages.sort_by(|a, b| a.name.cmp(&b.name));
     let pages = pages
         .into_iter()
         .filter_map(|page| page.load().ok())
-        .filter(|page| !page.is_hidden())
+        .filter(|page| page.is_listed_for(&viewer.audience(settings)))
         .collect::<Vec<_>>();
 
     let page = Layout {
         settings,
         title: "Index",
         head: Nil {},
-        body: Index { pages: &pages },
+        body: Index {
+            pages: &pages,
+            can_create: viewer.is_authenticated(),
+        },
     };
     Ok(CachedHtml::html(
+        policy,
         Page::last_modified_page(&settings.pages_path),
         page.to_string(),
     ))
@@ -0,0 +1,154 @@
+use std::time::SystemTime;
+
+use rocket::{Route, State};
+
+use crate::page::Page;
+use crate::render_cache::RenderCache;
+use crate::report;
+use crate::settings::{CacheClass, Settings};
+use crate::templates::report::{Collisions, Head, Index, Pages, Stale};
+use crate::templates::Layout;
+use crate::web::auth::Viewer;
+use crate::web::{CachePolicy, CachedHtml, IfModifiedSince};
+use crate::{return_if_fresh, PkbError};
+
+pub fn routes() -> Vec<Route> {
+    routes![index, orphans, untagged, stale, collisions]
+}
+
+// Includes the render cache statistics, which change on every request
+#[get("/reports")]
+pub(crate) fn index(
+    settings: &State<Settings>,
+    render_cache: &State<RenderCache>,
+) -> Result<CachedHtml, PkbError> {
+    let policy = CachePolicy::new(settings, CacheClass::Indexes);
+    let page = Layout {
+        settings,
+        title: "Reports",
+        head: Head {},
+        body: Index {
+            render_cache: render_cache.stats(),
+        },
+    };
+    Ok(CachedHtml::html(
+        policy,
+        SystemTime::now(),
+        page.to_string(),
+    ))
+}
+
+#[get("/reports/orphans")]
+pub(crate) fn orphans(
+    settings: &State<Settings>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedHtml, PkbError> {
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Indexes));
+    return_if_fresh!(
+        modified_since,
+        Page::last_modified_page(&settings.pages_path),
+        policy
+    );
+
+    let pages = report::orphans(&settings.pages_path, &viewer.audience(settings));
+    let page = Layout {
+        settings,
+        title: "Orphaned pages",
+        head: Head {},
+        body: Pages {
+            heading: "Orphaned pages",
+            description: "Pages without any tags that are not linked to from any other page.",
+            pages: &pages,
+        },
+    };
+    Ok(CachedHtml::html(
+        policy,
+        Page::last_modified_page(&settings.pages_path),
+        page.to_string(),
+    ))
+}
+
+#[get("/reports/untagged")]
+pub(crate) fn untagged(
+    settings: &State<Settings>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedHtml, PkbError> {
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Indexes));
+    return_if_fresh!(
+        modified_since,
+        Page::last_modified_page(&settings.pages_path),
+        policy
+    );
+
+    let pages = report::untagged(&settings.pages_path, &viewer.audience(settings));
+    let page = Layout {
+        settings,
+        title: "Untagged pages",
+        head: Head {},
+        body: Pages {
+            heading: "Untagged pages",
+            description: "Pages without any tags.",
+            pages: &pages,
+        },
+    };
+    Ok(CachedHtml::html(
+        policy,
+        Page::last_modified_page(&settings.pages_path),
+        page.to_string(),
+    ))
+}
+
+// Pages become stale with the passage of time, so this report is not conditional on the
+// modification time of the pages
+#[get("/reports/stale")]
+pub(crate) fn stale(settings: &State<Settings>, viewer: Viewer) -> Result<CachedHtml, PkbError> {
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Indexes));
+    let now = SystemTime::now();
+    let pages = report::stale(
+        &settings.pages_path,
+        &viewer.audience(settings),
+        settings.stale_after(),
+        now,
+    );
+    let page = Layout {
+        settings,
+        title: "Stale pages",
+        head: Head {},
+        body: Stale {
+            pages: &pages,
+            stale_after_days: settings.stale_after().as_secs() / (24 * 60 * 60),
+        },
+    };
+    Ok(CachedHtml::html(policy, now, page.to_string()))
+}
+
+#[get("/reports/collisions")]
+pub(crate) fn collisions(
+    settings: &State<Settings>,
+    viewer: Viewer,
+    modified_since: Option<IfModifiedSince>,
+) -> Result<CachedHtml, PkbError> {
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Indexes));
+    return_if_fresh!(
+        modified_since,
+        Page::last_modified_page(&settings.pages_path),
+        policy
+    );
+
+    let collisions = report::collisions(&settings.pages_path, Some(&viewer.audience(settings)));
+    let page = Layout {
+        settings,
+        title: "Slug collisions",
+        head: Head {},
+        body: Collisions {
+            collisions: &collisions,
+        },
+    };
+    Ok(CachedHtml::html(
+        policy,
+        Page::last_modified_page(&settings.pages_path),
+        page.to_string(),
+    ))
+}
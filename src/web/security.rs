@@ -0,0 +1,54 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Request, Response};
+
+use crate::settings::{SecuritySettings, Settings};
+
+/// Adds the security headers configured in `SecuritySettings` to every response
+pub(crate) struct SecurityHeaders;
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let settings = match request.rocket().state::<Settings>() {
+            Some(settings) => &settings.security,
+            None => return,
+        };
+
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        for (name, value) in headers(settings) {
+            // Responses may set their own policy, like the sandbox for SVG attachments
+            if !value.is_empty() && !response.headers().contains(name) {
+                response.set_raw_header(name, value);
+            }
+        }
+    }
+}
+
+fn headers(settings: &SecuritySettings) -> [(&'static str, String); 4] {
+    let hsts = settings
+        .hsts_max_age
+        .map(|max_age| {
+            if settings.hsts_include_subdomains {
+                format!("max-age={}; includeSubDomains", max_age)
+            } else {
+                format!("max-age={}", max_age)
+            }
+        })
+        .unwrap_or_default();
+    [
+        (
+            "Content-Security-Policy",
+            settings.content_security_policy.clone(),
+        ),
+        ("Referrer-Policy", settings.referrer_policy.clone()),
+        ("Permissions-Policy", settings.permissions_policy.clone()),
+        ("Strict-Transport-Security", hsts),
+    ]
+}
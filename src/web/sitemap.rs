@@ -1,4 +1,4 @@
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
 use rocket::http::uri::Origin;
 use rocket::response::content::RawXml;
@@ -8,14 +8,12 @@ use sitemap::structs::{ChangeFreq, LastMod, Location, Priority, UrlEntry};
 use sitemap::writer::SiteMapWriter;
 use time::OffsetDateTime;
 
-use crate::page::Page;
-use crate::settings::Settings;
+use crate::page::{Audience, Loaded, Page};
+use crate::settings::{CacheClass, Settings};
 use crate::tag::Tag;
-use crate::web::{self, cache_in_varnish, CacheControl};
+use crate::web::{self, cache_in_varnish, content_etag, tagged, CacheControl, CachePolicy, ETag};
 use crate::OffsetDateTimeExt;
 
-const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
-
 struct EntryFactory<'settings> {
     settings: &'settings Settings,
     buf: String,
@@ -33,7 +31,7 @@ pub(crate) fn robots(settings: &State<Settings>) -> String {
 #[get("/sitemap.xml")]
 pub(crate) fn show<'r>(
     settings: &State<Settings>,
-) -> Result<CacheControl<RawXml<Vec<u8>>>, Debug<sitemap::Error>> {
+) -> Result<CacheControl<ETag<RawXml<Vec<u8>>>>, Debug<sitemap::Error>> {
     // NOTE: Most of the sitemap methods can fail due to I/O errors but since our writer is
     // Vec<u8> we don't expect these in practice. As a result we return Debug<sitemap::Error>>
     // instead of setting up a custom error type that implements Responder.
@@ -45,7 +43,7 @@ pub(crate) fn show<'r>(
         buf: String::new(),
     };
 
-    if let Some(home) = Page::home(&settings.pages_path) {
+    if let Some(home) = Page::home(&settings.pages_path).and_then(|home| home.load().ok()) {
         let entry = factory.for_page(&home, uri!(web::home), 0.9);
         urlwriter.url(entry)?;
 
@@ -60,34 +58,32 @@ pub(crate) fn show<'r>(
 
     for page in Page::all(&settings.pages_path) {
         match page.load() {
-            Ok(page) if !page.is_hidden() => {
-                let entry = factory.for_page(&page, uri!(web::page::show(name = &page.name)), 1.0);
+            Ok(page) if page.is_listed_for(&Audience::anonymous(settings)) => {
+                let entry =
+                    factory.for_page(&page, uri!(web::page::show(name = &page.slug())), 1.0);
                 urlwriter.url(entry)?;
             }
             _ => {}
         }
     }
 
-    for tag in Tag::all(&settings.pages_path) {
+    for tag in Tag::all(&settings.pages_path, &Audience::anonymous(settings)) {
         let entry = factory.for_tag(&tag);
         urlwriter.url(entry)?;
     }
 
     let _ = urlwriter.end()?;
 
-    Ok(cache_in_varnish(ONE_DAY, RawXml(sitemap)))
+    let etag = content_etag(&sitemap);
+    let policy = CachePolicy::new(settings, CacheClass::Sitemap);
+    Ok(cache_in_varnish(policy, tagged(etag, RawXml(sitemap))))
 }
 
 impl<'settings> EntryFactory<'settings> {
-    fn for_page<T: std::fmt::Debug>(
-        &mut self,
-        page: &Page<T>,
-        path: Origin<'_>,
-        priority: f32,
-    ) -> UrlEntry {
+    fn for_page(&mut self, page: &Page<Loaded>, path: Origin<'_>, priority: f32) -> UrlEntry {
         UrlEntry {
             loc: self.loc(path),
-            lastmod: self.last_mod(page.last_modified(&self.settings.pages_path)),
+            lastmod: self.last_mod(page.last_modified(self.settings)),
             changefreq: ChangeFreq::Weekly,
             priority: Priority::Value(priority),
         }
@@ -1,11 +1,12 @@
 use rocket::{Route, State};
 
 use crate::page::Page;
-use crate::settings::Settings;
+use crate::settings::{CacheClass, Settings};
 use crate::tag::Tag;
 use crate::templates::tag::{Index, Show};
 use crate::templates::{Layout, Nil};
-use crate::web::{CachedHtml, IfModifiedSince};
+use crate::web::auth::Viewer;
+use crate::web::{CachePolicy, CachedHtml, IfModifiedSince};
 use crate::{return_if_fresh, PkbError};
 
 pub fn routes() -> Vec<Route> {
@@ -16,10 +17,13 @@ pub fn routes() -> Vec<Route> {
 pub(crate) fn show<'r>(
     name: &'r str,
     settings: &State<Settings>,
+    viewer: Viewer,
     modified_since: Option<IfModifiedSince>,
 ) -> Result<CachedHtml, PkbError> {
-    let tag = Tag::find(name, &settings.pages_path).ok_or(PkbError::PageNotFound)?;
-    return_if_fresh!(modified_since, tag.last_modified());
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Tags));
+    let tag = Tag::find(name, &settings.pages_path, &viewer.audience(settings))
+        .ok_or(PkbError::PageNotFound)?;
+    return_if_fresh!(modified_since, tag.last_modified(), policy);
 
     let page = Layout {
         settings,
@@ -27,18 +31,25 @@ pub(crate) fn show<'r>(
         head: Nil {},
         body: Show { tag: &tag },
     };
-    Ok(CachedHtml::html(tag.last_modified(), page.to_string()))
+    Ok(CachedHtml::html(
+        policy,
+        tag.last_modified(),
+        page.to_string(),
+    ))
 }
 
 #[get("/tags")]
 pub(crate) fn index<'r>(
     settings: &State<Settings>,
+    viewer: Viewer,
     modified_since: Option<IfModifiedSince>,
 ) -> Result<CachedHtml, PkbError> {
-    let tags = Tag::all(&settings.pages_path);
+    let policy = viewer.varying_policy(CachePolicy::new(settings, CacheClass::Tags));
+    let tags = Tag::all(&settings.pages_path, &viewer.audience(settings));
     return_if_fresh!(
         modified_since,
-        Page::last_modified_page(&settings.pages_path)
+        Page::last_modified_page(&settings.pages_path),
+        policy
     );
 
     let page = Layout {
@@ -48,6 +59,7 @@ pub(crate) fn index<'r>(
         body: Index { tags: &tags },
     };
     Ok(CachedHtml::html(
+        policy,
         Page::last_modified_page(&settings.pages_path),
         page.to_string(),
     ))